@@ -27,8 +27,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Got builds");
 
     // Pick a build, unset branch == master
-    let mut builds = builds.items().iter().filter(|b| b.branch().is_none());
-    let latest = builds.next().unwrap();
+    let latest = builds.latest_public(None).unwrap();
     println!("Picked latest build {}", latest.build_id());
 
     // Obtain the manifest, you should store it for later along with the build_id
@@ -43,6 +42,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let (dl, inst) = latest_manifest.install_size(
         &"en-US".to_owned(),
         [] as [&str; 0],
+        None,
         Some(&dependencies_manifest),
     );
 
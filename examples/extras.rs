@@ -0,0 +1,53 @@
+use gog_warp::content_system::downloader::progress::DownloadState;
+use gog_warp::FileDownloader;
+use std::env;
+use std::fs::read;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    simple_logger::init_with_env()?;
+    let core = gog_warp::Core::new();
+    let data = read(".gog.token").expect("Failed to load token, use auth example first");
+    let tokens_str = String::from_utf8(data).expect("Failed to parse Utf-8 sequence");
+    core.deserialize_tokens(&tokens_str)
+        .expect("failed to load tokens");
+
+    let product_id = env::args().nth(1).expect("Expected game id as parameter");
+    let extras = core.get_extras(&product_id).await?;
+    for extra in &extras {
+        println!(
+            "{} ({}) - {} bytes",
+            extra.name(),
+            extra.bonus_type(),
+            extra.total_size()
+        );
+    }
+
+    // Download all manuals
+    let files = extras
+        .iter()
+        .filter(|e| e.bonus_type() == "manuals")
+        .flat_map(|e| e.files().clone())
+        .collect();
+
+    let home = env::var("HOME").unwrap();
+    let mut downloader = FileDownloader::new(
+        core.clone(),
+        format!("{}/Games/warptest/extras", home).into(),
+        files,
+    );
+    let mut receiver = downloader.take_progress_receiver().unwrap();
+    let task = tokio::spawn(async move { downloader.download().await });
+
+    while let Some(message) = receiver.recv().await {
+        if let DownloadState::Downloading(state) = message {
+            println!("{}/{}", state.downloaded, state.total_download);
+        }
+    }
+
+    for path in task.await?? {
+        println!("Downloaded {}", path.display());
+    }
+
+    Ok(())
+}
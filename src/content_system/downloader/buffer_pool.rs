@@ -0,0 +1,98 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Size of a single buffer used to move inflated chunk data to disk
+pub(crate) const BUFFER_SIZE: usize = 256 * 1024;
+/// Memory available for chunk buffers when no limit is set
+pub(crate) const DEFAULT_MEMORY_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// Fixed number of reusable buffers  
+/// The number of buffers is derived from the memory limit,
+/// workers wait for a free buffer once all of them are in use
+pub(crate) struct BufferPool {
+    semaphore: Arc<Semaphore>,
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    /// Creates a pool using at most `memory_limit` bytes, at least one buffer is always available
+    pub fn new(memory_limit: u64) -> Arc<Self> {
+        let count = (memory_limit / BUFFER_SIZE as u64).max(1) as usize;
+        Arc::new(Self {
+            semaphore: Arc::new(Semaphore::new(count)),
+            free: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Waits for a free buffer
+    pub async fn get(self: &Arc<Self>) -> PooledBuffer {
+        let permit = self.semaphore.clone().acquire_owned().await.unwrap();
+        // Buffers are allocated lazily and kept for reuse
+        let buffer = self
+            .free
+            .lock()
+            .pop()
+            .unwrap_or_else(|| vec![0; BUFFER_SIZE]);
+        PooledBuffer {
+            buffer,
+            pool: self.clone(),
+            _permit: permit,
+        }
+    }
+}
+
+/// Buffer that goes back to the pool when dropped
+pub(crate) struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Arc<BufferPool>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let buffer = std::mem::take(&mut self.buffer);
+        self.pool.free.lock().push(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{timeout, Duration};
+
+    #[tokio::test]
+    async fn buffers_are_bounded_and_reused() {
+        let pool = BufferPool::new(2 * BUFFER_SIZE as u64);
+        let mut first = pool.get().await;
+        first[0] = 1;
+        let _second = pool.get().await;
+        assert!(timeout(Duration::from_millis(50), pool.get())
+            .await
+            .is_err());
+
+        drop(first);
+        let third = timeout(Duration::from_millis(50), pool.get())
+            .await
+            .unwrap();
+        assert_eq!(third.len(), BUFFER_SIZE);
+        assert_eq!(third[0], 1);
+        assert_eq!(pool.free.lock().len(), 0);
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use md5::{Digest, Md5};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::errors::io_error;
+use crate::utils::hash_to_galaxy_path;
+use crate::Error;
+
+static WRITER_ID: AtomicU64 = AtomicU64::new(0);
+
+struct CacheEntry {
+    size: u64,
+    last_used: SystemTime,
+}
+
+#[derive(Default)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    total_size: u64,
+}
+
+/// Content addressed store of compressed v2 chunks shared between installations  
+/// Chunks are kept by `compressed_md5` in the same layout the CDN uses,
+/// when the cache grows over its size limit the least recently used chunks are removed.
+#[derive(Clone)]
+pub struct ChunkCache {
+    root: PathBuf,
+    max_size: Option<u64>,
+    index: Arc<Mutex<CacheIndex>>,
+}
+
+impl ChunkCache {
+    /// Opens the cache at given directory, creating it if needed  
+    /// `max_size` is the size limit in bytes, None for unlimited
+    pub async fn open(root: PathBuf, max_size: Option<u64>) -> Result<Self, Error> {
+        fs::create_dir_all(&root).await.map_err(io_error)?;
+        let mut index = CacheIndex::default();
+        let mut dirs = vec![root.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut read_dir = fs::read_dir(&dir).await.map_err(io_error)?;
+            while let Some(item) = read_dir.next_entry().await.map_err(io_error)? {
+                let metadata = item.metadata().await.map_err(io_error)?;
+                if metadata.is_dir() {
+                    dirs.push(item.path());
+                    continue;
+                }
+                let name = item.file_name().to_string_lossy().to_string();
+                // Leftovers of interrupted writes
+                if name.ends_with(".tmp") {
+                    let _ = fs::remove_file(item.path()).await;
+                    continue;
+                }
+                index.total_size += metadata.len();
+                index.entries.insert(
+                    name,
+                    CacheEntry {
+                        size: metadata.len(),
+                        last_used: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    },
+                );
+            }
+        }
+        log::debug!(
+            "Opened chunk cache at {} with {} chunks",
+            root.display(),
+            index.entries.len()
+        );
+
+        let cache = Self {
+            root,
+            max_size,
+            index: Arc::new(Mutex::new(index)),
+        };
+        if let Some(max_size) = max_size {
+            cache.prune(max_size).await?;
+        }
+        Ok(cache)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn chunk_path(&self, compressed_md5: &str) -> PathBuf {
+        self.root.join(hash_to_galaxy_path(compressed_md5))
+    }
+
+    /// Total size of cached chunks in bytes
+    pub async fn size(&self) -> u64 {
+        self.index.lock().await.total_size
+    }
+
+    pub async fn contains(&self, compressed_md5: &str) -> bool {
+        self.index.lock().await.entries.contains_key(compressed_md5)
+    }
+
+    /// Returns the compressed chunk data if it's in the cache  
+    /// Corrupted chunks are removed
+    pub async fn get(&self, compressed_md5: &str) -> Option<Vec<u8>> {
+        if !self.contains(compressed_md5).await {
+            return None;
+        }
+        let path = self.chunk_path(compressed_md5);
+        // Chunk may have been evicted in the meantime
+        let data = fs::read(&path).await.ok()?;
+        let mut index = self.index.lock().await;
+        let data = match data {
+            data if format!("{:0x}", Md5::digest(&data)) == compressed_md5 => data,
+            _ => {
+                log::warn!("Removing invalid cached chunk {}", compressed_md5);
+                let _ = fs::remove_file(&path).await;
+                if let Some(entry) = index.entries.remove(compressed_md5) {
+                    index.total_size -= entry.size;
+                }
+                return None;
+            }
+        };
+        let now = SystemTime::now();
+        if let Some(entry) = index.entries.get_mut(compressed_md5) {
+            entry.last_used = now;
+        }
+        drop(index);
+        // Keep the usage time across restarts
+        if let Ok(file) = std::fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(now);
+        }
+        Some(data)
+    }
+
+    /// Opens the compressed chunk for reading if it's in the cache  
+    /// Unlike [`Self::get`] the data isn't verified, use [`Self::remove`] if it turns out to be invalid
+    pub(crate) async fn reader(&self, compressed_md5: &str) -> Option<fs::File> {
+        if !self.contains(compressed_md5).await {
+            return None;
+        }
+        let path = self.chunk_path(compressed_md5);
+        let file = fs::File::open(&path).await.ok()?;
+        let now = SystemTime::now();
+        if let Some(entry) = self.index.lock().await.entries.get_mut(compressed_md5) {
+            entry.last_used = now;
+        }
+        if let Ok(file) = std::fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(now);
+        }
+        Some(file)
+    }
+
+    /// Removes the chunk from the cache
+    pub async fn remove(&self, compressed_md5: &str) {
+        let mut index = self.index.lock().await;
+        let _ = fs::remove_file(self.chunk_path(compressed_md5)).await;
+        if let Some(entry) = index.entries.remove(compressed_md5) {
+            index.total_size -= entry.size;
+        }
+    }
+
+    /// Stores compressed chunk data, `data` has to match `compressed_md5`
+    pub async fn insert(&self, compressed_md5: &str, data: &[u8]) -> Result<(), Error> {
+        if self.contains(compressed_md5).await {
+            return Ok(());
+        }
+        let mut writer = self.writer(compressed_md5).await?;
+        writer.write(data).await?;
+        writer.commit().await
+    }
+
+    /// Starts storing a chunk that arrives in parts, see [`CacheWriter`]
+    pub(crate) async fn writer(&self, compressed_md5: &str) -> Result<CacheWriter, Error> {
+        let path = self.chunk_path(compressed_md5);
+        fs::create_dir_all(path.parent().unwrap())
+            .await
+            .map_err(io_error)?;
+        // Other downloaders may be writing the same chunk
+        let tmp_path = PathBuf::from(format!(
+            "{}.{}-{}.tmp",
+            path.display(),
+            std::process::id(),
+            WRITER_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs::File::create(&tmp_path).await.map_err(io_error)?;
+        Ok(CacheWriter {
+            cache: self.clone(),
+            compressed_md5: compressed_md5.to_owned(),
+            file: Some(file),
+            tmp_path,
+            path,
+            hasher: Md5::new(),
+            size: 0,
+        })
+    }
+
+    async fn add_entry(&self, compressed_md5: &str, size: u64) -> Result<(), Error> {
+        let mut index = self.index.lock().await;
+        if let Some(previous) = index.entries.insert(
+            compressed_md5.to_owned(),
+            CacheEntry {
+                size,
+                last_used: SystemTime::now(),
+            },
+        ) {
+            index.total_size -= previous.size;
+        }
+        index.total_size += size;
+        if let Some(max_size) = self.max_size {
+            evict(&self.root, &mut index, max_size).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes least recently used chunks until the cache is not bigger than `max_size`  
+    /// Returns the number of bytes freed
+    pub async fn prune(&self, max_size: u64) -> Result<u64, Error> {
+        let mut index = self.index.lock().await;
+        evict(&self.root, &mut index, max_size).await
+    }
+
+    /// Removes every cached chunk
+    pub async fn clear(&self) -> Result<u64, Error> {
+        self.prune(0).await
+    }
+}
+
+/// Chunk being written to the cache  
+/// The chunk is added once [`Self::commit`] confirms the data matches its md5,
+/// otherwise the partial file is removed
+pub(crate) struct CacheWriter {
+    cache: ChunkCache,
+    compressed_md5: String,
+    /// None once the writer is finished
+    file: Option<fs::File>,
+    tmp_path: PathBuf,
+    path: PathBuf,
+    hasher: Md5,
+    size: u64,
+}
+
+impl CacheWriter {
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        self.hasher.update(data);
+        self.size += data.len() as u64;
+        file.write_all(data).await.map_err(io_error)
+    }
+
+    pub async fn commit(mut self) -> Result<(), Error> {
+        let Some(mut file) = self.file.take() else {
+            return Ok(());
+        };
+        file.flush().await.map_err(io_error)?;
+        drop(file);
+        if format!("{:0x}", self.hasher.clone().finalize()) != self.compressed_md5 {
+            log::warn!(
+                "Not caching chunk {}, checksum mismatch",
+                self.compressed_md5
+            );
+            let _ = fs::remove_file(&self.tmp_path).await;
+            return Ok(());
+        }
+        fs::rename(&self.tmp_path, &self.path)
+            .await
+            .map_err(io_error)?;
+        self.cache.add_entry(&self.compressed_md5, self.size).await
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+async fn evict(root: &Path, index: &mut CacheIndex, max_size: u64) -> Result<u64, Error> {
+    if index.total_size <= max_size {
+        return Ok(0);
+    }
+    let mut entries: Vec<(String, SystemTime)> = index
+        .entries
+        .iter()
+        .map(|(md5, entry)| (md5.clone(), entry.last_used))
+        .collect();
+    entries.sort_by_key(|(_, last_used)| *last_used);
+
+    let mut freed = 0;
+    for (md5, _) in entries {
+        if index.total_size <= max_size {
+            break;
+        }
+        match fs::remove_file(root.join(hash_to_galaxy_path(&md5))).await {
+            Ok(_) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(io_error(err)),
+        }
+        let entry = index.entries.remove(&md5).unwrap();
+        index.total_size -= entry.size;
+        freed += entry.size;
+    }
+    log::debug!("Evicted {} bytes from chunk cache", freed);
+    Ok(freed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn md5(data: &[u8]) -> String {
+        format!("{:0x}", Md5::digest(data))
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used() {
+        let root = crate::utils::test_dir("chunk-cache");
+        let cache = ChunkCache::open(root.clone(), Some(8)).await.unwrap();
+
+        let (a, b, c) = (b"aaaa", b"bbbb", b"cccc");
+        cache.insert(&md5(a), a).await.unwrap();
+        cache.insert(&md5(b), b).await.unwrap();
+        assert_eq!(cache.get(&md5(a)).await.unwrap(), a);
+        cache.insert(&md5(c), c).await.unwrap();
+
+        assert!(cache.contains(&md5(a)).await);
+        assert!(!cache.contains(&md5(b)).await);
+        assert!(cache.contains(&md5(c)).await);
+        assert_eq!(cache.size().await, 8);
+        assert!(root.join(hash_to_galaxy_path(&md5(c))).exists());
+
+        // Index is restored from disk
+        let cache = ChunkCache::open(root.clone(), None).await.unwrap();
+        assert_eq!(cache.size().await, 8);
+        assert_eq!(cache.prune(4).await.unwrap(), 4);
+        assert_eq!(cache.clear().await.unwrap(), 4);
+        assert_eq!(cache.size().await, 0);
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+
+use tokio::sync::{watch, Mutex};
+
+use crate::Error;
+
+/// Where the downloaded copy of a chunk was written
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ChunkLocation {
+    /// File being downloaded
+    pub download_path: PathBuf,
+    /// Path of the file once it's complete
+    pub destination_path: PathBuf,
+    pub offset: u64,
+}
+
+enum Slot {
+    /// Chunk is being downloaded, the receiver is notified once it's done
+    Pending(watch::Receiver<Option<ChunkLocation>>),
+    /// Chunk location waiting for the remaining files
+    Ready {
+        location: ChunkLocation,
+        remaining: usize,
+    },
+}
+
+/// Index of chunks shared between files of the download  
+/// Each shared chunk is downloaded once, the remaining files copy it
+/// from the file it was written to
+#[derive(Default)]
+pub(crate) struct ChunkIndex {
+    /// Number of uses and compressed size of every chunk by compressed md5
+    counts: HashMap<String, (usize, u64)>,
+    slots: Mutex<HashMap<String, Slot>>,
+}
+
+impl ChunkIndex {
+    pub fn add(&mut self, compressed_md5: &str, compressed_size: u64) {
+        let entry = self
+            .counts
+            .entry(compressed_md5.to_owned())
+            .or_insert((0, compressed_size));
+        entry.0 += 1;
+    }
+
+    /// Whether the chunk is used by more than one file
+    pub fn is_shared(&self, compressed_md5: &str) -> bool {
+        self.counts
+            .get(compressed_md5)
+            .is_some_and(|(count, _)| *count > 1)
+    }
+
+    /// Compressed size of chunk copies that won't be downloaded
+    pub fn duplicated_size(&self) -> u64 {
+        self.counts
+            .values()
+            .fold(0, |acc, (count, size)| acc + (*count as u64 - 1) * size)
+    }
+
+    /// Returns the location of the chunk, downloading it with `fetch` if nobody did it yet  
+    /// The boolean is true if the chunk was downloaded by this call
+    pub async fn get_or_fetch<F, Fut>(
+        &self,
+        compressed_md5: &str,
+        fetch: F,
+    ) -> Result<(ChunkLocation, bool), Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<ChunkLocation, Error>>,
+    {
+        loop {
+            let mut slots = self.slots.lock().await;
+            match slots.get_mut(compressed_md5) {
+                None => {
+                    let (tx, rx) = watch::channel(None);
+                    slots.insert(compressed_md5.to_owned(), Slot::Pending(rx));
+                    drop(slots);
+
+                    let location = match fetch().await {
+                        Ok(location) => location,
+                        Err(err) => {
+                            // Let one of the waiting files try again
+                            self.slots.lock().await.remove(compressed_md5);
+                            return Err(err);
+                        }
+                    };
+                    let remaining = self
+                        .counts
+                        .get(compressed_md5)
+                        .map(|(count, _)| count.saturating_sub(1))
+                        .unwrap_or_default();
+                    let mut slots = self.slots.lock().await;
+                    if remaining > 0 {
+                        slots.insert(
+                            compressed_md5.to_owned(),
+                            Slot::Ready {
+                                location: location.clone(),
+                                remaining,
+                            },
+                        );
+                    } else {
+                        slots.remove(compressed_md5);
+                    }
+                    drop(slots);
+                    let _ = tx.send(Some(location.clone()));
+                    return Ok((location, true));
+                }
+                Some(Slot::Pending(rx)) => {
+                    let mut rx = rx.clone();
+                    drop(slots);
+                    // Error means the download failed, the slot is gone in that case
+                    let _ = rx.changed().await;
+                }
+                Some(Slot::Ready {
+                    location,
+                    remaining,
+                }) => {
+                    let location = location.clone();
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        slots.remove(compressed_md5);
+                    }
+                    return Ok((location, false));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn location() -> ChunkLocation {
+        ChunkLocation {
+            download_path: PathBuf::from("a.download"),
+            destination_path: PathBuf::from("a"),
+            offset: 3,
+        }
+    }
+
+    #[tokio::test]
+    async fn shared_chunk_fetched_once() {
+        let mut index = ChunkIndex::default();
+        index.add("a", 10);
+        index.add("a", 10);
+        index.add("a", 10);
+        index.add("b", 5);
+        assert!(index.is_shared("a"));
+        assert!(!index.is_shared("b"));
+        assert_eq!(index.duplicated_size(), 20);
+
+        let index = Arc::new(index);
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let mut handles = Vec::new();
+        for _ in 0..3 {
+            let index = index.clone();
+            let fetches = fetches.clone();
+            handles.push(tokio::spawn(async move {
+                index
+                    .get_or_fetch("a", || async {
+                        fetches.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                        Ok(location())
+                    })
+                    .await
+                    .unwrap()
+            }));
+        }
+        let mut fetched = 0;
+        for handle in handles {
+            let (found, was_fetched) = handle.await.unwrap();
+            assert_eq!(found, location());
+            fetched += was_fetched as usize;
+        }
+        assert_eq!(fetched, 1);
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert!(index.slots.lock().await.is_empty());
+    }
+}
@@ -0,0 +1,281 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use super::pause::Pause;
+use super::progress::DownloadProgress;
+
+pub(crate) const DEFAULT_MAX_FILES: usize = 3;
+pub(crate) const DEFAULT_MAX_CHUNKS: usize = 6;
+/// Upper bound of chunk concurrency in adaptive mode
+pub(crate) const ADAPTIVE_MAX_CHUNKS: usize = 32;
+/// How often the adaptive mode re-evaluates the limits
+const ADAPTIVE_PERIOD: Duration = Duration::from_secs(5);
+
+struct LimitState {
+    limit: usize,
+    /// Permits to remove once they are released by the workers
+    debt: usize,
+}
+
+/// Semaphore with adjustable number of permits
+pub(crate) struct Limit {
+    semaphore: Arc<Semaphore>,
+    state: Arc<Mutex<LimitState>>,
+}
+
+/// Permit of a [`Limit`]  
+/// When the limit was lowered while the permit was held, it's forgotten instead of released
+pub(crate) struct LimitPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    state: Arc<Mutex<LimitState>>,
+}
+
+impl Drop for LimitPermit {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        let permit = self.permit.take().unwrap();
+        if state.debt > 0 {
+            state.debt -= 1;
+            permit.forget();
+        }
+    }
+}
+
+impl Limit {
+    fn new(limit: usize) -> Self {
+        let limit = limit.max(1);
+        Self {
+            semaphore: Arc::new(Semaphore::new(limit)),
+            state: Arc::new(Mutex::new(LimitState { limit, debt: 0 })),
+        }
+    }
+
+    pub fn get(&self) -> usize {
+        self.state.lock().limit
+    }
+
+    fn set(&self, limit: usize) {
+        let limit = limit.max(1);
+        let mut state = self.state.lock();
+        if limit > state.limit {
+            let grow = limit - state.limit;
+            let paid = grow.min(state.debt);
+            state.debt -= paid;
+            self.semaphore.add_permits(grow - paid);
+        } else {
+            let shrink = state.limit - limit;
+            let forgotten = self.semaphore.forget_permits(shrink);
+            state.debt += shrink - forgotten;
+        }
+        state.limit = limit;
+    }
+
+    pub async fn acquire(&self) -> LimitPermit {
+        let permit = self.semaphore.clone().acquire_owned().await.unwrap();
+        LimitPermit {
+            permit: Some(permit),
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// Number of files and chunks downloaded at once  
+/// Changes apply to downloads in progress
+#[derive(Clone)]
+pub struct Concurrency {
+    files: Arc<Limit>,
+    chunks: Arc<Limit>,
+    adaptive: Arc<AtomicBool>,
+}
+
+impl Default for Concurrency {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FILES, DEFAULT_MAX_CHUNKS)
+    }
+}
+
+impl Concurrency {
+    pub fn new(max_files: usize, max_chunks: usize) -> Self {
+        Self {
+            files: Arc::new(Limit::new(max_files)),
+            chunks: Arc::new(Limit::new(max_chunks)),
+            adaptive: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn max_files(&self) -> usize {
+        self.files.get()
+    }
+
+    pub fn max_chunks(&self) -> usize {
+        self.chunks.get()
+    }
+
+    pub fn set_max_files(&self, max_files: usize) {
+        self.files.set(max_files)
+    }
+
+    /// Sets the number of chunks downloaded at once across all files
+    pub fn set_max_chunks(&self, max_chunks: usize) {
+        self.chunks.set(max_chunks)
+    }
+
+    /// When enabled the limits are tuned based on observed throughput and errors  
+    /// Manually set limits are used as the starting point
+    pub fn set_adaptive(&self, adaptive: bool) {
+        self.adaptive.store(adaptive, Ordering::Relaxed)
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.adaptive.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn files(&self) -> Arc<Limit> {
+        self.files.clone()
+    }
+
+    pub(crate) fn chunks(&self) -> Arc<Limit> {
+        self.chunks.clone()
+    }
+
+    /// Applies the adaptive step, file concurrency follows the chunk concurrency
+    pub(crate) fn adapt(&self, state: &mut AdaptiveState, throughput: f32, retries: u64) {
+        let chunks = state.next(self.max_chunks(), throughput, retries);
+        if chunks != self.max_chunks() {
+            log::debug!("Adjusting chunk concurrency to {}", chunks);
+            self.set_max_chunks(chunks);
+            self.set_max_files((chunks / 2).max(1));
+        }
+    }
+}
+
+/// Hill climbing over chunk concurrency  
+/// More connections are tried while they improve the throughput,
+/// errors cut the concurrency down
+#[derive(Default)]
+pub(crate) struct AdaptiveState {
+    /// Throughput observed in the previous period
+    last_throughput: f32,
+    /// Whether the last change was an increase
+    grew: bool,
+}
+
+impl AdaptiveState {
+    /// Returns new chunk concurrency for the observed throughput and number of retries
+    pub fn next(&mut self, chunks: usize, throughput: f32, retries: u64) -> usize {
+        let next = if retries > 0 {
+            (chunks * 3 / 4).max(1)
+        } else if self.grew && throughput < self.last_throughput * 1.05 {
+            // The last increase didn't help
+            (chunks - 1).max(1)
+        } else if throughput > 0.0 {
+            (chunks + 2).min(ADAPTIVE_MAX_CHUNKS)
+        } else {
+            chunks
+        };
+        self.grew = next > chunks;
+        self.last_throughput = throughput;
+        next
+    }
+}
+
+/// Spawns a task that tunes `concurrency` while it's adaptive  
+/// The task runs until `cancellation_token` is cancelled
+pub(crate) fn spawn_tuner(
+    concurrency: Concurrency,
+    pause: Pause,
+    download_progress: Arc<tokio::sync::Mutex<DownloadProgress>>,
+    cancellation_token: CancellationToken,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut state = AdaptiveState::default();
+        let mut last_retries = 0;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(ADAPTIVE_PERIOD) => {}
+                _ = cancellation_token.cancelled() => break,
+            }
+            let (throughput, retries) = {
+                let progress = download_progress.lock().await;
+                (progress.avg_network, progress.retries)
+            };
+            // Throughput of a paused download says nothing about the limits
+            if concurrency.is_adaptive() && !pause.is_paused() {
+                concurrency.adapt(&mut state, throughput, retries - last_retries);
+            }
+            last_retries = retries;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::time::sleep;
+
+    #[tokio::test]
+    async fn limit_changes_apply_to_held_permits() {
+        let limit = Limit::new(2);
+        let first = limit.acquire().await;
+        let second = limit.acquire().await;
+        limit.set(1);
+        assert_eq!(limit.semaphore.available_permits(), 0);
+        drop(first);
+        drop(second);
+        let permit = limit.acquire().await;
+        assert_eq!(limit.semaphore.available_permits(), 0);
+
+        limit.set(3);
+        assert_eq!(limit.semaphore.available_permits(), 2);
+        drop(permit);
+
+        // Released permits don't go to queued waiters while the limit is lowered
+        let limit = Arc::new(Limit::new(2));
+        let first = limit.acquire().await;
+        let second = limit.acquire().await;
+        let acquired = Arc::new(AtomicUsize::new(0));
+        let mut waiters = Vec::new();
+        for _ in 0..3 {
+            let limit = limit.clone();
+            let acquired = acquired.clone();
+            waiters.push(tokio::spawn(async move {
+                let _permit = limit.acquire().await;
+                acquired.fetch_add(1, Ordering::SeqCst);
+                sleep(Duration::from_secs(10)).await;
+            }));
+        }
+        sleep(Duration::from_millis(20)).await;
+
+        limit.set(1);
+        drop(first);
+        drop(second);
+        sleep(Duration::from_millis(20)).await;
+        // Only one of the waiters got the remaining permit
+        assert_eq!(acquired.load(Ordering::SeqCst), 1);
+
+        limit.set(2);
+        sleep(Duration::from_millis(20)).await;
+        assert_eq!(acquired.load(Ordering::SeqCst), 2);
+        for waiter in waiters {
+            waiter.abort();
+        }
+    }
+
+    #[test]
+    fn adaptive_steps() {
+        let mut state = AdaptiveState::default();
+        assert_eq!(state.next(6, 1000.0, 0), 8);
+        assert_eq!(state.next(8, 2000.0, 0), 10);
+        // No improvement, step back
+        assert_eq!(state.next(10, 2010.0, 0), 9);
+        assert_eq!(state.next(9, 2000.0, 0), 11);
+        assert_eq!(state.next(11, 2000.0, 3), 8);
+    }
+}
@@ -1,20 +1,71 @@
 use std::collections::{HashMap, HashSet};
 
+use crate::content_system::patches::PatchPolicy;
 use crate::content_system::types::{traits::EntryUtils, v1, v2, DepotEntry, FileList};
 
+/// How many bytes read from the disk cost as much as one downloaded byte
+const SOURCE_READ_COST_RATIO: u64 = 10;
+
 #[derive(Debug, Clone)]
 pub struct Patch {
     pub(crate) product_id: String,
     pub(crate) diff: v2::DepotEntry,
+    /// Diffs applied after `diff` when patching through intermediate builds
+    pub(crate) chain: Vec<v2::DepotEntry>,
     pub(crate) destination_file: v2::DepotEntry,
 }
 
+impl Patch {
+    /// All of the diffs in order they need to be applied
+    pub(crate) fn diffs(&self) -> impl Iterator<Item = &v2::DepotEntry> {
+        std::iter::once(&self.diff).chain(self.chain.iter())
+    }
+}
+
+/// Explains why the file was patched or downloaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchDecision {
+    pub path: String,
+    /// Compressed size of the diffs with the cost of reading the source file
+    pub patch_cost: u64,
+    /// Compressed size of the new file
+    pub download_cost: u64,
+    pub patched: bool,
+    /// Whether the choice was made by [`PatchPolicy`] regardless of the costs
+    pub forced: bool,
+}
+
+impl PatchDecision {
+    pub fn reason(&self) -> String {
+        let action = if self.patched {
+            "patching"
+        } else {
+            "downloading"
+        };
+        if self.forced {
+            format!("{}: {} enforced by the patch policy", self.path, action)
+        } else {
+            format!(
+                "{}: {} is cheaper (patch {} vs download {})",
+                self.path, action, self.patch_cost, self.download_cost
+            )
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct DiffReport {
     pub(crate) download: Vec<FileList>,
     pub(crate) patches: Vec<Patch>,
     pub(crate) directories: Vec<DepotEntry>,
     pub(crate) deleted: Vec<DepotEntry>,
+    /// Unchanged files which executable flag changed
+    pub(crate) permissions: Vec<FileList>,
+    /// Choices made for every file that had a patch available
+    pub(crate) patch_decisions: Vec<PatchDecision>,
+    /// Chunks of the installed versions of changed files, by lowercase path  
+    /// Unchanged chunks are copied locally instead of being downloaded
+    pub(crate) reusable_chunks: HashMap<String, Vec<v2::Chunk>>,
     pub(crate) number_of_files: u32,
 }
 
@@ -32,6 +83,7 @@ pub fn diff(
     new_entries: Vec<FileList>,
     old_entries: Vec<FileList>,
     patches: Vec<FileList>,
+    policy: PatchPolicy,
 ) -> DiffReport {
     let new = map_list(&new_entries);
     let old = map_list(&old_entries);
@@ -49,12 +101,24 @@ pub fn diff(
                 if let DepotEntry::V2(v2_entry) = patch {
                     let file_path = v2_entry.path();
                     let new_file = new.get(&file_path.to_lowercase()).cloned().unwrap();
-                    patched_files.insert(file_path.to_lowercase());
+                    // Following diffs of the same file are next steps of the chain
+                    if !patched_files.insert(file_path.to_lowercase()) {
+                        if let Some(patch) = report
+                            .patches
+                            .iter_mut()
+                            .rev()
+                            .find(|p| p.diff.path().to_lowercase() == file_path.to_lowercase())
+                        {
+                            patch.chain.push(v2_entry);
+                        }
+                        continue;
+                    }
 
                     if let DepotEntry::V2(v2_file) = new_file {
                         let patch_report = Patch {
                             product_id: list.product_id.clone(),
                             diff: v2_entry,
+                            chain: Vec::new(),
                             destination_file: v2_file.to_owned(),
                         };
                         report.patches.push(patch_report);
@@ -64,6 +128,35 @@ pub fn diff(
         }
     }
 
+    // Choose between the patch and downloading the whole file
+    let mut decisions = Vec::new();
+    report.patches.retain(|patch| {
+        let path = patch.diff.path().to_lowercase();
+        let source_size = old.get(&path).map(|f| f.size()).unwrap_or_default() as u64;
+        let patch_cost = patch
+            .diffs()
+            .fold(0, |acc, d| acc + d.compressed_size() as u64)
+            + source_size / SOURCE_READ_COST_RATIO;
+        let download_cost = patch.destination_file.compressed_size() as u64;
+        let (patched, forced) = match policy {
+            PatchPolicy::Auto => (patch_cost <= download_cost, false),
+            PatchPolicy::PatchesOnly => (true, true),
+            PatchPolicy::NeverPatch => (false, true),
+        };
+        if !patched {
+            patched_files.remove(&path);
+        }
+        decisions.push(PatchDecision {
+            path: patch.destination_file.path(),
+            patch_cost,
+            download_cost,
+            patched,
+            forced,
+        });
+        patched
+    });
+    report.patch_decisions = decisions;
+
     report.number_of_files += report.patches.len() as u32;
 
     for old_file in old.keys() {
@@ -156,6 +249,19 @@ pub fn diff(
                         && nf.sha256() == of.sha256())
                 {
                     final_download.remove(new_path);
+                    continue;
+                }
+
+                // Remember the old chunks if some of them can be reused
+                if nf.chunks().len() > 1
+                    && nf
+                        .chunks()
+                        .iter()
+                        .any(|nc| of.chunks().iter().any(|oc| oc.md5() == nc.md5()))
+                {
+                    report
+                        .reusable_chunks
+                        .insert(new_path.clone(), of.chunks().clone());
                 }
             }
 
@@ -163,6 +269,18 @@ pub fn diff(
         }
     }
 
+    let mut mode_changed: HashSet<String> = HashSet::new();
+    for (new_path, new_file) in new.iter() {
+        if final_download.contains(new_path) || patched_files.contains(new_path) {
+            continue;
+        }
+        if let Some(old_file) = old.get(new_path) {
+            if new_file.is_executable() != old_file.is_executable() {
+                mode_changed.insert(new_path.clone());
+            }
+        }
+    }
+
     drop(new);
 
     for file_list in new_entries {
@@ -170,8 +288,15 @@ pub fn diff(
         new_list.is_dependency = file_list.is_dependency;
         new_list.is_global_dependency = file_list.is_global_dependency;
         let mut needs_sfc: bool = false;
+        let mut permissions_list = FileList::new(new_list.product_id.clone(), Vec::new());
+        permissions_list.is_dependency = file_list.is_dependency;
+        permissions_list.is_global_dependency = file_list.is_global_dependency;
 
         for entry in file_list.files {
+            if mode_changed.remove(&entry.path().to_lowercase()) {
+                permissions_list.files.push(entry);
+                continue;
+            }
             if final_download.remove(&entry.path().to_lowercase()) {
                 if !needs_sfc {
                     if let DepotEntry::V2(v2::DepotEntry::File(file)) = &entry {
@@ -188,6 +313,9 @@ pub fn diff(
             report.number_of_files += new_list.files.len() as u32;
             report.download.push(new_list)
         }
+        if !permissions_list.files.is_empty() {
+            report.permissions.push(permissions_list)
+        }
     }
 
     // Track down deleted files
@@ -201,3 +329,103 @@ pub fn diff(
 
     report
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v2_file(path: &str, md5: &str, flags: &[&str]) -> DepotEntry {
+        let entry = serde_json::json!({
+            "type": "DepotFile",
+            "path": path,
+            "md5": md5,
+            "flags": flags,
+            "chunks": [{"compressedMd5": "c", "md5": md5, "size": 10, "compressedSize": 5}]
+        });
+        DepotEntry::V2(serde_json::from_value(entry).unwrap())
+    }
+
+    #[test]
+    fn executable_flag_change() {
+        let old = vec![FileList::new(
+            "1".to_owned(),
+            vec![v2_file("game.bin", "a", &[]), v2_file("data.pak", "b", &[])],
+        )];
+        let new = vec![FileList::new(
+            "1".to_owned(),
+            vec![
+                v2_file("game.bin", "a", &["executable"]),
+                v2_file("data.pak", "b", &[]),
+            ],
+        )];
+
+        let report = diff(new, old, Vec::new(), PatchPolicy::Auto);
+        assert!(report.download.is_empty());
+        assert_eq!(report.permissions.len(), 1);
+        assert_eq!(report.permissions[0].files.len(), 1);
+        assert_eq!(report.permissions[0].files[0].path(), "game.bin");
+        assert!(report.permissions[0].files[0].is_executable());
+    }
+
+    fn v2_diff(path: &str, compressed_size: i64) -> DepotEntry {
+        let entry = serde_json::json!({
+            "type": "DepotDiff",
+            "md5_source": "a",
+            "md5_target": "b",
+            "path_source": path,
+            "path_target": path,
+            "md5": "d",
+            "chunks": [{"compressedMd5": "c", "md5": "m", "size": compressed_size, "compressedSize": compressed_size}]
+        });
+        DepotEntry::V2(serde_json::from_value(entry).unwrap())
+    }
+
+    #[test]
+    fn patch_cost_decisions() {
+        let old = || {
+            vec![FileList::new(
+                "1".to_owned(),
+                vec![v2_file("small.bin", "a", &[]), v2_file("big.bin", "a", &[])],
+            )]
+        };
+        let new = || {
+            vec![FileList::new(
+                "1".to_owned(),
+                vec![v2_file("small.bin", "b", &[]), v2_file("big.bin", "b", &[])],
+            )]
+        };
+        // New files are 5 bytes compressed
+        let patches = || {
+            vec![FileList::new(
+                "1".to_owned(),
+                vec![v2_diff("small.bin", 2), v2_diff("big.bin", 20)],
+            )]
+        };
+
+        let report = diff(new(), old(), patches(), PatchPolicy::Auto);
+        assert_eq!(report.patches.len(), 1);
+        assert_eq!(report.patches[0].diff.path(), "small.bin");
+        assert_eq!(report.download.len(), 1);
+        assert_eq!(report.download[0].files[0].path(), "big.bin");
+        let big = report
+            .patch_decisions
+            .iter()
+            .find(|d| d.path == "big.bin")
+            .unwrap();
+        assert!(!big.patched && !big.forced);
+        assert_eq!(big.patch_cost, 21);
+        assert_eq!(big.download_cost, 5);
+
+        let report = diff(new(), old(), patches(), PatchPolicy::PatchesOnly);
+        assert_eq!(report.patches.len(), 2);
+        assert!(report.download.is_empty());
+
+        let report = diff(new(), old(), patches(), PatchPolicy::NeverPatch);
+        assert!(report.patches.is_empty());
+        assert_eq!(report.download[0].files.len(), 2);
+        assert!(report
+            .patch_decisions
+            .iter()
+            .all(|d| d.forced && !d.patched));
+    }
+}
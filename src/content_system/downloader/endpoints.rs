@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use parking_lot::Mutex;
+
+use crate::content_system::secure_link;
+use crate::content_system::types::Endpoint;
+use crate::{Core, Error};
+
+/// How long before the expiry the links are renewed
+const RENEWAL_MARGIN_SECS: i64 = 5 * 60;
+
+/// Failure counts of the endpoints shared by all workers of a download
+#[derive(Default)]
+pub(crate) struct EndpointPool {
+    /// Consecutive failures by endpoint name
+    failures: Mutex<HashMap<String, u32>>,
+}
+
+impl EndpointPool {
+    /// Endpoints of a product that use the failure counts of this pool  
+    /// Links are renewed using `source` when they expire
+    pub fn endpoints(
+        self: &Arc<Self>,
+        endpoints: Vec<Endpoint>,
+        source: Option<LinkSource>,
+    ) -> PooledEndpoints {
+        PooledEndpoints {
+            links: Arc::new(Mutex::new(Links {
+                endpoints: Arc::new(endpoints),
+                generation: 0,
+            })),
+            refresh_lock: Arc::new(tokio::sync::Mutex::new(())),
+            source: source.map(Arc::new),
+            pool: self.clone(),
+        }
+    }
+
+    fn is_demoted(&self, endpoint: &Endpoint) -> bool {
+        let failures = self.failures.lock();
+        let count = failures
+            .get(endpoint.endpoint_name())
+            .copied()
+            .unwrap_or_default();
+        *endpoint.max_fails() > 0 && count >= *endpoint.max_fails()
+    }
+
+    fn report_failure(&self, endpoint: &Endpoint) {
+        let mut failures = self.failures.lock();
+        let count = failures
+            .entry(endpoint.endpoint_name().clone())
+            .or_default();
+        *count += 1;
+        if *count == *endpoint.max_fails() {
+            log::warn!(
+                "Endpoint {} failed {} times, demoting it",
+                endpoint.endpoint_name(),
+                count
+            );
+        }
+    }
+
+    fn report_success(&self, endpoint: &Endpoint) {
+        let mut failures = self.failures.lock();
+        if let Some(count) = failures.get_mut(endpoint.endpoint_name()) {
+            *count = 0;
+        }
+    }
+}
+
+/// Puts the mirror endpoint in front of the CDN endpoints
+pub(crate) fn with_mirror(
+    mut endpoints: Vec<Endpoint>,
+    mirror: Option<&str>,
+    manifest_version: u8,
+) -> Vec<Endpoint> {
+    let Some(base_url) = mirror else {
+        return endpoints;
+    };
+    if manifest_version != 2 {
+        return endpoints;
+    }
+    let store_path = endpoints
+        .first()
+        .and_then(|e| e.parameters().get("path"))
+        .and_then(|p| p.as_str())
+        .map(|p| p.to_owned());
+    if let Some(store_path) = store_path {
+        endpoints.insert(0, Endpoint::mirror(base_url, &store_path));
+    }
+    endpoints
+}
+
+/// Parameters needed to obtain the secure link again
+pub(crate) struct LinkSource {
+    pub core: Core,
+    pub manifest_version: u8,
+    /// None for dependencies
+    pub product_id: Option<String>,
+    pub path: String,
+    pub root: String,
+    pub mirror: Option<String>,
+}
+
+impl LinkSource {
+    pub async fn fetch(&self) -> Result<Vec<Endpoint>, Error> {
+        let endpoints = match &self.product_id {
+            Some(product_id) => {
+                let token = self.core.obtain_galaxy_token().await?;
+                secure_link::get_secure_link(
+                    self.core.reqwest_client(),
+                    self.manifest_version,
+                    product_id,
+                    &token,
+                    &self.path,
+                    &self.root,
+                )
+                .await?
+            }
+            None => secure_link::get_dependencies_link(self.core.reqwest_client()).await?,
+        };
+        Ok(with_mirror(
+            endpoints,
+            self.mirror.as_deref(),
+            self.manifest_version,
+        ))
+    }
+}
+
+struct Links {
+    endpoints: Arc<Vec<Endpoint>>,
+    /// Incremented with every renewal
+    generation: u64,
+}
+
+/// Endpoints of a single product
+#[derive(Clone)]
+pub(crate) struct PooledEndpoints {
+    links: Arc<Mutex<Links>>,
+    /// Makes sure only one worker renews the links
+    refresh_lock: Arc<tokio::sync::Mutex<()>>,
+    source: Option<Arc<LinkSource>>,
+    pool: Arc<EndpointPool>,
+}
+
+impl PooledEndpoints {
+    /// Endpoints in the order they should be tried and the generation of the links  
+    /// Healthy endpoints go first by priority, then `fallback_only` ones,
+    /// endpoints that exceeded `max_fails` are used as the last resort
+    pub fn ordered(&self) -> (Vec<Endpoint>, u64) {
+        let links = self.links.lock();
+        let mut endpoints: Vec<Endpoint> = links.endpoints.iter().cloned().collect();
+        let generation = links.generation;
+        drop(links);
+        endpoints.sort_by_key(|e| {
+            (
+                self.pool.is_demoted(e),
+                *e.fallback_only(),
+                std::cmp::Reverse(*e.priority()),
+            )
+        });
+        (endpoints, generation)
+    }
+
+    pub fn report_failure(&self, endpoint: &Endpoint) {
+        self.pool.report_failure(endpoint)
+    }
+
+    pub fn report_success(&self, endpoint: &Endpoint) {
+        self.pool.report_success(endpoint)
+    }
+
+    /// Earliest expiry time of the links
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.links
+            .lock()
+            .endpoints
+            .iter()
+            .filter_map(|e| e.expires_at())
+            .min()
+    }
+
+    /// Renews the links if they expire soon
+    pub async fn refresh_if_expiring(&self) {
+        let Some(expires_at) = self.expires_at() else {
+            return;
+        };
+        if expires_at - Utc::now() > TimeDelta::seconds(RENEWAL_MARGIN_SECS) {
+            return;
+        }
+        let generation = self.links.lock().generation;
+        if let Err(err) = self.refresh(generation).await {
+            log::warn!("Failed to renew expiring secure link: {}", err);
+        }
+    }
+
+    /// Obtains new links, unless they were renewed since `generation`  
+    /// Returns false if links can't be renewed
+    pub async fn refresh(&self, generation: u64) -> Result<bool, Error> {
+        let Some(source) = &self.source else {
+            return Ok(false);
+        };
+        let _guard = self.refresh_lock.lock().await;
+        if self.links.lock().generation != generation {
+            return Ok(true);
+        }
+        log::info!(
+            "Renewing secure link for {}",
+            source.product_id.as_deref().unwrap_or("dependencies")
+        );
+        let endpoints = source.fetch().await?;
+        let mut links = self.links.lock();
+        links.endpoints = Arc::new(endpoints);
+        links.generation += 1;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(name: &str, priority: u32, max_fails: u32, fallback_only: bool) -> Endpoint {
+        serde_json::from_value(serde_json::json!({
+            "endpoint_name": name,
+            "url_format": "{base_url}",
+            "parameters": {},
+            "priority": priority,
+            "max_fails": max_fails,
+            "supports_generation": [2],
+            "fallback_only": fallback_only
+        }))
+        .unwrap()
+    }
+
+    fn names(endpoints: &PooledEndpoints) -> Vec<String> {
+        endpoints
+            .ordered()
+            .0
+            .into_iter()
+            .map(|e| e.endpoint_name().clone())
+            .collect()
+    }
+
+    #[test]
+    fn endpoint_failover_order() {
+        let pool = Arc::new(EndpointPool::default());
+        let endpoints = pool.endpoints(
+            vec![
+                endpoint("akamai", 500, 2, false),
+                endpoint("lumen", 100, 2, true),
+                endpoint("fastly", 998, 2, false),
+            ],
+            None,
+        );
+        assert_eq!(names(&endpoints), vec!["fastly", "akamai", "lumen"]);
+
+        let fastly = endpoints.ordered().0[0].clone();
+        endpoints.report_failure(&fastly);
+        assert_eq!(names(&endpoints), vec!["fastly", "akamai", "lumen"]);
+        endpoints.report_failure(&fastly);
+        assert_eq!(names(&endpoints), vec!["akamai", "lumen", "fastly"]);
+
+        // Failures are shared between products
+        let other = pool.endpoints(vec![endpoint("fastly", 998, 2, false)], None);
+        assert!(pool.is_demoted(&other.ordered().0[0]));
+
+        endpoints.report_success(&fastly);
+        assert_eq!(names(&endpoints), vec!["fastly", "akamai", "lumen"]);
+    }
+
+    #[tokio::test]
+    async fn links_without_source_are_not_renewed() {
+        let pool = Arc::new(EndpointPool::default());
+        let mut expiring = endpoint("fastly", 998, 2, false);
+        expiring
+            .parameters
+            .insert("expires_at".to_string(), serde_json::json!("1717011195"));
+        let endpoints = pool.endpoints(vec![expiring, endpoint("akamai", 500, 2, false)], None);
+        assert_eq!(endpoints.expires_at().unwrap().timestamp(), 1717011195);
+
+        endpoints.refresh_if_expiring().await;
+        assert!(!endpoints.refresh(0).await.unwrap());
+        assert_eq!(endpoints.ordered().1, 0);
+    }
+}
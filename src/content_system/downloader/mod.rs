@@ -1,9 +1,7 @@
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
 
-use crate::content_system::secure_link;
 use crate::errors::{cancelled_error, task_error};
 use crate::{
     errors::{dbuilder_error, io_error, not_ready_error},
@@ -12,21 +10,40 @@ use crate::{
 
 use tokio::fs;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
-use tokio::sync::mpsc::{error::TryRecvError, Receiver, Sender};
-use tokio::sync::{Mutex, Semaphore};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 
+use self::buffer_pool::{BufferPool, DEFAULT_MEMORY_LIMIT};
+use self::chunk_index::ChunkIndex;
+use self::endpoints::{EndpointPool, LinkSource, PooledEndpoints};
+use self::pause::Pause;
 use self::progress::{load_chunk_state, DownloadState, WorkerUpdate};
 
 use super::dependencies::DependenciesManifest;
-use super::types::{traits::EntryUtils, Endpoint, Manifest};
-use super::types::{v1, v2, DepotEntry};
-
+use super::patches::PatchPolicy;
+use super::types::{traits::EntryUtils, Manifest, OsBitness};
+use super::types::{v1, v2, DepotEntry, FileList};
+use super::updates::{EstimateOptions, UpdateEstimate};
+
+mod buffer_pool;
+mod chunk_cache;
+pub use chunk_cache::ChunkCache;
+mod chunk_index;
+mod concurrency;
+pub use concurrency::Concurrency;
 mod diff;
+mod endpoints;
+pub use diff::PatchDecision;
 mod patching;
+mod pause;
+mod queue;
+pub use queue::{DownloadJob, DownloadQueue, JobId, JobState, JobStatus, QueueEvent};
 pub mod progress;
-mod utils;
-mod verify;
+mod speed_limiter;
+pub use speed_limiter::SpeedLimiter;
+pub(crate) mod utils;
+pub(crate) mod verify;
 mod worker;
 
 #[derive(Default)]
@@ -46,6 +63,15 @@ pub struct Builder {
     dlcs: Vec<String>,
     old_dlcs: Vec<String>,
     verify: bool,
+    offline_depot: bool,
+    bitness: Option<OsBitness>,
+    patch_policy: PatchPolicy,
+    branch_password: Option<String>,
+    chunk_cache: Option<ChunkCache>,
+    mirror: Option<String>,
+    speed_limiter: Option<SpeedLimiter>,
+    concurrency: Option<Concurrency>,
+    memory_limit: Option<u64>,
 }
 
 impl Builder {
@@ -97,6 +123,12 @@ impl Builder {
         let dlcs = self.dlcs;
         let old_dlcs = self.old_dlcs;
         let verify = self.verify;
+        let offline_depot = self.offline_depot;
+        let bitness = self.bitness;
+        let patch_policy = self.patch_policy;
+        let branch_password = self.branch_password;
+        let chunk_cache = self.chunk_cache;
+        let mirror = self.mirror;
         let dependency_manifest = self.dependency_manifest;
 
         if (!old_dlcs.is_empty() || language != old_language) && old_manifest.is_none() {
@@ -117,6 +149,12 @@ impl Builder {
             dlcs,
             old_dlcs,
             verify,
+            offline_depot,
+            bitness,
+            patch_policy,
+            branch_password,
+            chunk_cache,
+            mirror,
             build_id,
             prev_build_id,
             progress_channel_sender,
@@ -125,7 +163,10 @@ impl Builder {
             global_dependencies_root,
             dependency_manifest,
             download_report: None,
-            max_speed: Mutex::new(-1),
+            speed_limiter: self.speed_limiter.unwrap_or_default(),
+            concurrency: self.concurrency.unwrap_or_default(),
+            memory_limit: self.memory_limit.unwrap_or(DEFAULT_MEMORY_LIMIT),
+            pause: Pause::default(),
         })
     }
 
@@ -221,6 +262,70 @@ impl Builder {
         self.verify = true;
         self
     }
+
+    /// Whether to install the offline depot of v2 manifests  
+    /// It contains files required to run the game without Galaxy, excluded by default
+    pub fn offline_depot(mut self, include: bool) -> Self {
+        self.offline_depot = include;
+        self
+    }
+
+    /// Install only depots meant for given architecture  
+    /// By default depots for all architectures are installed
+    pub fn bitness(mut self, bitness: OsBitness) -> Self {
+        self.bitness = Some(bitness);
+        self
+    }
+
+    /// Controls whether updated files are patched or downloaded  
+    /// By default the cheaper option is picked for every file
+    pub fn patch_policy(mut self, patch_policy: PatchPolicy) -> Self {
+        self.patch_policy = patch_policy;
+        self
+    }
+
+    /// Password of the private branch the builds belong to  
+    /// Needed to find patches through intermediate builds of that branch
+    pub fn branch_password(mut self, password: String) -> Self {
+        self.branch_password = Some(password);
+        self
+    }
+
+    /// Reuse chunks from a local cache before downloading them and store downloaded chunks in it  
+    /// The same cache can be shared between multiple downloaders
+    pub fn chunk_cache(mut self, chunk_cache: ChunkCache) -> Self {
+        self.chunk_cache = Some(chunk_cache);
+        self
+    }
+
+    /// Base url of a local mirror, see [`crate::content_system::types::Endpoint::mirror`]  
+    /// The mirror is used before the CDN for v2 content
+    pub fn mirror(mut self, base_url: String) -> Self {
+        self.mirror = Some(base_url);
+        self
+    }
+
+    /// Limit the download speed with given limiter  
+    /// Pass clones of the same limiter to multiple downloaders to cap their total speed
+    pub fn speed_limiter(mut self, speed_limiter: SpeedLimiter) -> Self {
+        self.speed_limiter = Some(speed_limiter);
+        self
+    }
+
+    /// Number of files and chunks downloaded at once, defaults to 3 files and 6 chunks  
+    /// See [`Concurrency::set_adaptive`] to tune the limits automatically
+    pub fn concurrency(mut self, concurrency: Concurrency) -> Self {
+        self.concurrency = Some(concurrency);
+        self
+    }
+
+    /// Memory in bytes used for chunk data in flight, defaults to 64MiB  
+    /// Chunks are streamed to disk through a pool of 256KiB buffers,
+    /// lower limits mean less chunks are processed at once
+    pub fn memory_limit(mut self, memory_limit: u64) -> Self {
+        self.memory_limit = Some(memory_limit);
+        self
+    }
 }
 
 /// The main component responsible for downloading game files
@@ -253,6 +358,18 @@ pub struct Downloader {
     global_dependencies_root: PathBuf,
     /// Whether to verify the files based on the manifest
     verify: bool,
+    /// Whether to include the offline depot
+    offline_depot: bool,
+    /// Architecture of depots to install
+    bitness: Option<OsBitness>,
+    /// When to use patches
+    patch_policy: PatchPolicy,
+    /// Password of the private branch
+    branch_password: Option<String>,
+    /// Local cache of compressed chunks
+    chunk_cache: Option<ChunkCache>,
+    /// Base url of the local mirror
+    mirror: Option<String>,
     /// Manifest to use for dependencies
     dependency_manifest: Option<DependenciesManifest>,
 
@@ -261,7 +378,11 @@ pub struct Downloader {
 
     cancellation_token: CancellationToken,
     download_report: Option<diff::DiffReport>,
-    max_speed: Mutex<i32>,
+    speed_limiter: SpeedLimiter,
+    concurrency: Concurrency,
+    /// Memory limit of chunk buffers in bytes
+    memory_limit: u64,
+    pause: Pause,
 }
 
 impl Downloader {
@@ -281,8 +402,45 @@ impl Downloader {
         self.progress_channel_receiver.take()
     }
 
+    /// Sets the download speed limit in bytes per second, values <= 0 disable it  
+    /// Can be changed while downloading, the limit is shared with other downloaders
+    /// using the same [`SpeedLimiter`]
     pub async fn set_max_speed(&self, speed: i32) {
-        *self.max_speed.lock().await = speed;
+        self.speed_limiter.set_max_speed(speed);
+    }
+
+    pub fn speed_limiter(&self) -> SpeedLimiter {
+        self.speed_limiter.clone()
+    }
+
+    /// Returns a handle to the concurrency limits, changes apply to the download in progress
+    pub fn concurrency(&self) -> Concurrency {
+        self.concurrency.clone()
+    }
+
+    /// Suspends the download in place  
+    /// Workers stop at the next chunk or network read, secure links, progress and
+    /// partially downloaded files are kept, so [`Self::resume`] continues where it stopped.  
+    /// Emits [`DownloadState::Paused`], the event is skipped if the progress channel is full
+    pub async fn pause(&self) {
+        if self.pause.set_paused(true) {
+            log::info!("Pausing the download");
+            let _ = self.progress_channel_sender.try_send(DownloadState::Paused);
+        }
+    }
+
+    /// Continues the paused download, emits [`DownloadState::Resumed`] like [`Self::pause`]
+    pub async fn resume(&self) {
+        if self.pause.set_paused(false) {
+            log::info!("Resuming the download");
+            let _ = self
+                .progress_channel_sender
+                .try_send(DownloadState::Resumed);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause.is_paused()
     }
 
     /// Fetches file lists and patches manifest
@@ -295,8 +453,14 @@ impl Downloader {
         let mut depots = match &self.manifest {
             Some(m) => {
                 log::trace!("Getting depots for main manifest");
-                m.get_depots(self.core.reqwest_client(), &self.language, &self.dlcs)
-                    .await?
+                m.get_depots(
+                    self.core.reqwest_client(),
+                    &self.language,
+                    &self.dlcs,
+                    self.bitness.as_ref(),
+                    self.offline_depot,
+                )
+                .await?
             }
             None => Vec::new(),
         };
@@ -308,6 +472,8 @@ impl Downloader {
                     self.core.reqwest_client(),
                     &self.old_language,
                     &self.old_dlcs,
+                    self.bitness.as_ref(),
+                    self.offline_depot,
                 )
                 .await?
             }
@@ -343,27 +509,66 @@ impl Downloader {
             .cloned()
             .collect();
 
-        let patches = super::patches::get_patches(
-            self.core.reqwest_client(),
+        let patches = match (
             &self.manifest,
             &self.build_id,
             &self.old_manifest,
-            self.prev_build_id.clone(),
-            re_used_dlcs,
-            &self.language,
-            &self.old_language,
-        )
-        .await?;
+            &self.prev_build_id,
+        ) {
+            (
+                Some(manifest @ Manifest::V2(_)),
+                Some(build_id),
+                Some(Manifest::V2(_)),
+                Some(prev_build_id),
+            ) => {
+                let filter = super::patches::PatchFilter {
+                    dlcs: &re_used_dlcs,
+                    new_language: &self.language,
+                    old_language: &self.old_language,
+                    bitness: self.bitness.as_ref(),
+                    policy: self.patch_policy,
+                    branch_password: self.branch_password.as_ref(),
+                };
+                super::patches::plan_patches(
+                    self.core.reqwest_client(),
+                    manifest,
+                    build_id,
+                    prev_build_id,
+                    &filter,
+                    &depots,
+                    &old_depots,
+                )
+                .await?
+            }
+            _ => None,
+        };
 
-        let results = diff::diff(depots, old_depots, patches.unwrap_or_default());
+        let results = diff::diff(
+            depots,
+            old_depots,
+            patches.unwrap_or_default(),
+            self.patch_policy,
+        );
         self.download_report = Some(results);
         Ok(())
     }
 
+    /// Explains the choice between patching and downloading for each file with a patch  
+    /// Available after [`Self::prepare`]
+    pub fn patch_decisions(&self) -> Option<&[PatchDecision]> {
+        self.download_report
+            .as_ref()
+            .map(|r| r.patch_decisions.as_slice())
+    }
+
     /// Return space required for operation to complete, takes in account pre-allocated files
     /// You should check if you have enough space before calling `download`
-    pub async fn get_required_space(&mut self) -> Result<i64, Error> {
-        let report = self.download_report.take().unwrap();
+    pub async fn get_required_space(&self) -> Result<i64, Error> {
+        let Some(report) = &self.download_report else {
+            return Err(not_ready_error(
+                "download not ready, did you forget Downloader::prepare()?",
+            ));
+        };
         let mut size_total: i64 = 0;
         // Since we want to allow the game to be playable after pausing the update
         // we are not subtracting deleted files sizes
@@ -402,11 +607,11 @@ impl Downloader {
             let file_path = file_root.join(patch.diff.path());
             let status = self.get_file_status(&file_path).await;
             if matches!(status, progress::DownloadFileStatus::NotInitialized) {
-                size_total += patch.diff.size() + patch.destination_file.size();
+                size_total += patch.diffs().fold(0, |acc, d| acc + d.size());
+                size_total += patch.destination_file.size();
             }
         }
 
-        self.download_report = Some(report);
         Ok(size_total)
     }
 
@@ -564,12 +769,14 @@ impl Downloader {
         let mut new_symlinks: Vec<(String, String)> = Vec::new();
         let mut ready_files: HashSet<String> = HashSet::new();
         let mut ready_patches: HashSet<String> = HashSet::new();
-        let secure_links: Arc<Mutex<HashMap<String, Vec<Endpoint>>>> =
+        let endpoint_pool = Arc::new(EndpointPool::default());
+        let secure_links: Arc<Mutex<HashMap<String, PooledEndpoints>>> =
             Arc::new(Mutex::new(HashMap::new()));
 
         let mut download_progress: progress::DownloadProgress = Default::default();
 
         let mut allocated_files: u32 = 0;
+        let mut chunk_index = ChunkIndex::default();
 
         log::info!("Allocating disk space");
         // Allocate disk space, generate secure links and restore progress state
@@ -679,7 +886,27 @@ impl Downloader {
                     continue;
                 }
 
-                match self.get_file_status(&file_path).await {
+                let status = self.get_file_status(&file_path).await;
+                if let (DepotEntry::V2(v2::DepotEntry::File(f)), false) = (entry, is_sfc_contained)
+                {
+                    let done = match &status {
+                        progress::DownloadFileStatus::Partial(chunks_state) => chunks_state.clone(),
+                        progress::DownloadFileStatus::NotInitialized
+                        | progress::DownloadFileStatus::Allocated => Vec::new(),
+                        _ => vec![true; f.chunks.len()],
+                    };
+                    let reusable = report.reusable_chunks.get(&entry_path.to_lowercase());
+                    for (index, chunk) in f.chunks.iter().enumerate() {
+                        let local =
+                            reusable.is_some_and(|r| r.iter().any(|c| c.md5() == chunk.md5()));
+                        if !done.get(index).unwrap_or(&false) && !local {
+                            chunk_index
+                                .add(chunk.compressed_md5(), *chunk.compressed_size() as u64);
+                        }
+                    }
+                }
+
+                match status {
                     progress::DownloadFileStatus::NotInitialized
                     | progress::DownloadFileStatus::Allocated => {
                         let allocation_file = format!("{}.download", file_path.to_str().unwrap());
@@ -736,21 +963,16 @@ impl Downloader {
                     product_id,
                     file_list.is_dependency
                 );
-                let endpoints = if file_list.is_dependency {
-                    secure_link::get_dependencies_link(self.core.reqwest_client()).await?
-                } else {
-                    let token = self.core.obtain_galaxy_token().await?;
-                    secure_link::get_secure_link(
-                        self.core.reqwest_client(),
-                        manifest_version,
-                        &product_id,
-                        &token,
-                        &path,
-                        "",
-                    )
-                    .await?
+                let source = LinkSource {
+                    core: self.core.clone(),
+                    manifest_version,
+                    product_id: (!file_list.is_dependency).then(|| product_id.clone()),
+                    path: path.clone(),
+                    root: String::new(),
+                    mirror: self.mirror.clone(),
                 };
-                e.insert(endpoints);
+                let endpoints = source.fetch().await?;
+                e.insert(endpoint_pool.endpoints(endpoints, Some(source)));
             }
         }
 
@@ -769,25 +991,40 @@ impl Downloader {
 
             let mut secure_links = secure_links.lock().await;
             if !secure_links.contains_key(&product_id) {
-                let token = self.core.obtain_galaxy_token().await?;
                 log::info!("Getting patch secure_link for {}", patch.product_id);
-                let endpoints = secure_link::get_secure_link(
-                    self.core.reqwest_client(),
+                let source = LinkSource {
+                    core: self.core.clone(),
                     manifest_version,
-                    &patch.product_id,
-                    &token,
-                    "/",
-                    "/patches/store",
-                )
-                .await?;
-                secure_links.insert(product_id.clone(), endpoints);
+                    product_id: Some(patch.product_id.clone()),
+                    path: "/".to_owned(),
+                    root: "/patches/store".to_owned(),
+                    mirror: self.mirror.clone(),
+                };
+                let endpoints = source.fetch().await?;
+                secure_links.insert(
+                    product_id.clone(),
+                    endpoint_pool.endpoints(endpoints, Some(source)),
+                );
             }
 
             download_progress.total_download += entry.compressed_size() as u64;
             download_progress.total_size += entry.size() as u64;
             download_progress.total_size += patch.destination_file.size() as u64;
+            let status = self.get_file_status(&file_path).await;
+            // Diffs of an applied chain are already removed
+            if !matches!(status, progress::DownloadFileStatus::Done) {
+                for (index, hop) in patch.chain.iter().enumerate() {
+                    download_progress.total_download += hop.compressed_size() as u64;
+                    download_progress.total_size += hop.size() as u64;
+                    let hop_path = utils::chain_diff_path(&file_path, index + 1);
+                    if hop_path.exists() {
+                        download_progress.downloaded += hop.compressed_size() as u64;
+                        download_progress.written += hop.size() as u64;
+                    }
+                }
+            }
 
-            match self.get_file_status(&file_path).await {
+            match status {
                 progress::DownloadFileStatus::NotInitialized
                 | progress::DownloadFileStatus::Allocated => {
                     let file_path = file_path.to_str().unwrap();
@@ -831,47 +1068,35 @@ impl Downloader {
             }
         }
 
+        // Shared chunks are downloaded once
+        download_progress.total_download = download_progress
+            .total_download
+            .saturating_sub(chunk_index.duplicated_size());
+        let chunk_index = Arc::new(chunk_index);
+
         let download_progress = Arc::new(Mutex::new(download_progress));
 
-        let file_semaphore = Arc::new(Semaphore::new(3));
-        let chunk_semaphore = Arc::new(Semaphore::new(6));
+        let file_limit = self.concurrency.files();
+        let chunk_limit = self.concurrency.chunks();
+        let buffers = BufferPool::new(self.memory_limit);
 
-        // TODO: Handle download speed reports
-        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<WorkerUpdate>();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<WorkerUpdate>();
         let mut handles = tokio::task::JoinSet::new();
 
-        let report_download_progress = download_progress.clone();
-        let progress_channel_sender = self.progress_channel_sender.clone();
-        let progress_report = tokio::spawn(async move {
-            let mut timestamp = tokio::time::Instant::now();
-            let one_sec = tokio::time::Duration::from_secs(1);
-
-            loop {
-                match rx.try_recv() {
-                    Ok(message) => {
-                        let mut progress = report_download_progress.lock().await;
-                        match message {
-                            WorkerUpdate::Download(size) => progress.downloaded += size as u64,
-                            WorkerUpdate::Write(size) => progress.written += size as u64,
-                        }
-                        if timestamp.elapsed() > tokio::time::Duration::from_millis(500) {
-                            timestamp = tokio::time::Instant::now();
-                            let _ = progress_channel_sender
-                                .try_send(DownloadState::Downloading((*progress).clone()));
-                        }
-                    }
-                    Err(TryRecvError::Disconnected) => break,
-                    Err(TryRecvError::Empty) => tokio::time::sleep(Duration::from_millis(50)).await,
-                }
-            }
-            let progress = report_download_progress.lock().await;
-            let _ = progress_channel_sender
-                .send_timeout(DownloadState::Downloading((*progress).clone()), one_sec)
-                .await;
-            let _ = progress_channel_sender
-                .send_timeout(DownloadState::Finished, one_sec)
-                .await;
-        });
+        let progress_report = progress::spawn_reporter(
+            download_progress.clone(),
+            rx,
+            self.progress_channel_sender.clone(),
+        );
+        // Stops the tuner when the download ends, including on errors
+        let tuner_token = self.cancellation_token.child_token();
+        let _tuner_guard = tuner_token.clone().drop_guard();
+        concurrency::spawn_tuner(
+            self.concurrency.clone(),
+            self.pause.clone(),
+            download_progress.clone(),
+            tuner_token,
+        );
 
         // Spawn download tasks
         for list in &report.download {
@@ -888,14 +1113,18 @@ impl Downloader {
 
                     let product_id = list.product_id();
                     let secure_links = secure_links.clone();
-                    let chunk_semaphore = chunk_semaphore.clone();
+                    let chunk_limit = chunk_limit.clone();
+                    let buffers = buffers.clone();
                     let chunks = sfc.chunks().clone();
-                    let file_semaphore = file_semaphore.clone();
+                    let file_limit = file_limit.clone();
                     let path = chunk.md5().clone();
+                    let chunk_cache = self.chunk_cache.clone();
+                    let speed_limiter = self.speed_limiter.clone();
+                    let pause = self.pause.clone();
                     let reqwest_client = self.core.reqwest_client().clone();
                     let tx = tx.clone();
                     handles.spawn(async move {
-                        let file_permit = file_semaphore.acquire_owned().await.unwrap();
+                        let file_permit = file_limit.acquire().await;
                         let secure_links = secure_links.lock().await;
                         let endpoints = secure_links.get(&product_id).unwrap().clone();
                         drop(secure_links);
@@ -903,7 +1132,8 @@ impl Downloader {
                         worker::v2(
                             file_permit,
                             reqwest_client,
-                            chunk_semaphore,
+                            chunk_limit,
+                            buffers,
                             endpoints,
                             v2::DepotEntry::File(v2::DepotFile {
                                 chunks,
@@ -915,6 +1145,10 @@ impl Downloader {
                             }),
                             file_path,
                             tx,
+                            None,
+                            chunk_cache,
+                            speed_limiter,
+                            pause,
                         )
                         .await
                     });
@@ -943,16 +1177,46 @@ impl Downloader {
                                 continue;
                             }
                         }
-                        let file_semaphore = file_semaphore.clone();
+                        let file_limit = file_limit.clone();
                         let secure_links = secure_links.clone();
 
-                        let chunk_semaphore = chunk_semaphore.clone();
+                        let chunk_limit = chunk_limit.clone();
+                        let buffers = buffers.clone();
                         let reqwest_client = self.core.reqwest_client().clone();
                         let v2_entry = v2_entry.clone();
                         let tx = tx.clone();
                         let product_id = list.product_id();
+                        let chunk_index = chunk_index.clone();
+                        let chunk_cache = self.chunk_cache.clone();
+                        let speed_limiter = self.speed_limiter.clone();
+                        let pause = self.pause.clone();
+                        // Installed version of the file to copy unchanged chunks from
+                        let reusable =
+                            report
+                                .reusable_chunks
+                                .get(&file.path().to_lowercase())
+                                .map(|chunks| {
+                                    let root = self.get_file_root(
+                                        file.is_support(),
+                                        list.is_global_dependency,
+                                        &list.product_id,
+                                        true,
+                                    );
+                                    (root.join(file.path()), chunks.clone())
+                                });
                         handles.spawn(async move {
-                            let file_permit = file_semaphore.clone().acquire_owned().await.unwrap();
+                            let file_permit = file_limit.acquire().await;
+                            if let Some((source_path, old_chunks)) = reusable {
+                                worker::copy_local_chunks(
+                                    &source_path,
+                                    &old_chunks,
+                                    &v2_entry,
+                                    &file_path,
+                                    &buffers,
+                                    &tx,
+                                )
+                                .await?;
+                            }
                             let secure_links = secure_links.lock().await;
                             let endpoints = secure_links.get(&product_id).unwrap().clone();
                             drop(secure_links);
@@ -960,25 +1224,32 @@ impl Downloader {
                             worker::v2(
                                 file_permit,
                                 reqwest_client,
-                                chunk_semaphore,
+                                chunk_limit,
+                                buffers,
                                 endpoints,
                                 v2_entry,
                                 file_path,
                                 tx,
+                                Some(chunk_index),
+                                chunk_cache,
+                                speed_limiter,
+                                pause,
                             )
                             .await
                         });
                     }
                     DepotEntry::V1(v1_entry) => {
-                        let file_semaphore = file_semaphore.clone();
+                        let file_limit = file_limit.clone();
                         let secure_links = secure_links.clone();
 
                         let product_id = list.product_id();
                         let reqwest_client = self.core.reqwest_client().clone();
                         let v1_entry = v1_entry.clone();
                         let tx = tx.clone();
+                        let speed_limiter = self.speed_limiter.clone();
+                        let pause = self.pause.clone();
                         handles.spawn(async move {
-                            let file_permit = file_semaphore.clone().acquire_owned().await.unwrap();
+                            let file_permit = file_limit.acquire().await;
                             let secure_links = secure_links.lock().await;
                             let endpoints = secure_links.get(&product_id).unwrap().clone();
                             drop(secure_links);
@@ -989,6 +1260,8 @@ impl Downloader {
                                 v1_entry,
                                 file_path,
                                 tx,
+                                speed_limiter,
+                                pause,
                             )
                             .await
                         });
@@ -999,46 +1272,65 @@ impl Downloader {
 
         for patch in &report.patches {
             let file = &patch.diff;
-            let entry_path = file.path();
-            if ready_patches.contains(&entry_path) {
+            let entry_root = self.get_file_root(file.is_support(), false, &patch.product_id, false);
+            let base_path = entry_root.join(file.path());
+            if ready_files.contains(&file.path()) {
                 continue;
             }
-            let entry_path = format!("{}.diff", entry_path);
-            let entry_root = self.get_file_root(file.is_support(), false, &patch.product_id, false);
-            let file_path = entry_root.join(&entry_path);
 
-            let file_semaphore = file_semaphore.clone();
-            let secure_links = secure_links.clone();
+            for (index, diff) in patch.diffs().enumerate() {
+                let file_path = utils::chain_diff_path(&base_path, index);
+                if index == 0 && ready_patches.contains(&file.path()) {
+                    continue;
+                }
+                if index > 0 && file_path.exists() {
+                    continue;
+                }
 
-            let chunk_semaphore = chunk_semaphore.clone();
-            let reqwest_client = self.core.reqwest_client().clone();
-            let v2_entry = file.clone();
-            let tx = tx.clone();
-            let product_id = format!("{}patch", patch.product_id);
-            handles.spawn(async move {
-                let file_permit = file_semaphore.clone().acquire_owned().await.unwrap();
-                let secure_links = secure_links.lock().await;
-                let endpoints = secure_links.get(&product_id).unwrap().clone();
-                drop(secure_links);
-
-                worker::v2(
-                    file_permit,
-                    reqwest_client,
-                    chunk_semaphore,
-                    endpoints,
-                    v2_entry,
-                    file_path,
-                    tx,
-                )
-                .await
-            });
+                let file_limit = file_limit.clone();
+                let secure_links = secure_links.clone();
+
+                let chunk_limit = chunk_limit.clone();
+                let buffers = buffers.clone();
+                let reqwest_client = self.core.reqwest_client().clone();
+                let v2_entry = diff.clone();
+                let tx = tx.clone();
+                let product_id = format!("{}patch", patch.product_id);
+                let chunk_cache = self.chunk_cache.clone();
+                let speed_limiter = self.speed_limiter.clone();
+                let pause = self.pause.clone();
+                handles.spawn(async move {
+                    let file_permit = file_limit.acquire().await;
+                    let secure_links = secure_links.lock().await;
+                    let endpoints = secure_links.get(&product_id).unwrap().clone();
+                    drop(secure_links);
+
+                    worker::v2(
+                        file_permit,
+                        reqwest_client,
+                        chunk_limit,
+                        buffers,
+                        endpoints,
+                        v2_entry,
+                        file_path,
+                        tx,
+                        None,
+                        chunk_cache,
+                        speed_limiter,
+                        pause,
+                    )
+                    .await
+                });
+            }
         }
 
+        let _ = tx.send(WorkerUpdate::FilesQueued(handles.len()));
         loop {
             tokio::select! {
                 result = handles.join_next() => {
                     match result {
                         Some(result) => {
+                            let _ = tx.send(WorkerUpdate::FileFinished);
                             let join_res = result.map_err(task_error);
                             if join_res.is_err() {
                                 handles.shutdown().await;
@@ -1131,39 +1423,68 @@ impl Downloader {
                 let tmp_root = self.get_file_root(false, false, &patch.product_id, false);
                 let dst_root = self.get_file_root(false, false, &patch.product_id, true);
 
-                let diff_path = format!("{}.diff", file_path);
-
-                let source_file_path = dst_root.join(&file_path);
-                let diff_file_path = tmp_root.join(diff_path);
+                let base_path = tmp_root.join(&file_path);
                 let target_file_path = tmp_root.join(format!("{}.patched", file_path));
+                let mut source_file_path = dst_root.join(&file_path);
+                let steps = patch.chain.len() + 1;
+                let step_target_path = |step: usize| {
+                    if step + 1 == steps {
+                        target_file_path.clone()
+                    } else {
+                        PathBuf::from(format!("{}.{}", target_file_path.display(), step))
+                    }
+                };
 
-                let input_file = std::fs::OpenOptions::new()
-                    .read(true)
-                    .open(&diff_file_path)
-                    .map_err(io_error)?;
+                // Apply the chain one diff at a time, intermediate results are kept next to the target
+                // The diffs are only removed once the whole chain is applied,
+                // so an interrupted chain is applied again from the start
+                if !base_path.exists() {
+                    for step in 0..steps {
+                        let diff_file_path = utils::chain_diff_path(&base_path, step);
+                        let is_last = step + 1 == steps;
+                        let step_target_path = step_target_path(step);
+
+                        let input_file = std::fs::OpenOptions::new()
+                            .read(true)
+                            .open(&diff_file_path)
+                            .map_err(io_error)?;
 
-                let src_file = std::fs::OpenOptions::new()
-                    .read(true)
-                    .open(source_file_path)
-                    .map_err(io_error)?;
+                        let src_file = std::fs::OpenOptions::new()
+                            .read(true)
+                            .open(&source_file_path)
+                            .map_err(io_error)?;
 
-                let target_file = std::fs::OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .truncate(false)
-                    .open(&target_file_path)
-                    .map_err(io_error)?;
+                        let target_file = std::fs::OpenOptions::new()
+                            .create(true)
+                            .write(true)
+                            .truncate(!is_last)
+                            .open(&step_target_path)
+                            .map_err(io_error)?;
 
-                let tx = tx.clone();
-                tokio::task::spawn_blocking(|| {
-                    patching::patch_file(input_file, src_file, target_file, tx)
-                })
-                .await
-                .unwrap()?;
-                fs::rename(target_file_path, tmp_root.join(file_path))
-                    .await
-                    .map_err(io_error)?;
-                fs::remove_file(diff_file_path).await.map_err(io_error)?;
+                        // Only the final output counts towards the written bytes
+                        let step_tx = if is_last {
+                            tx.clone()
+                        } else {
+                            tokio::sync::mpsc::unbounded_channel().0
+                        };
+                        tokio::task::spawn_blocking(|| {
+                            patching::patch_file(input_file, src_file, target_file, step_tx)
+                        })
+                        .await
+                        .unwrap()?;
+                        source_file_path = step_target_path;
+                    }
+                    fs::rename(&target_file_path, &base_path)
+                        .await
+                        .map_err(io_error)?;
+                }
+
+                for step in 0..steps {
+                    let _ = fs::remove_file(utils::chain_diff_path(&base_path, step)).await;
+                    if step + 1 < steps {
+                        let _ = fs::remove_file(step_target_path(step)).await;
+                    }
+                }
             }
         }
 
@@ -1220,6 +1541,31 @@ impl Downloader {
             fs::remove_dir_all(tmp_root).await.map_err(io_error)?;
         }
 
+        // Apply permissions, this also repairs modes of already present files
+        for list in report.download.iter().chain(report.permissions.iter()) {
+            for file in &list.files {
+                if !matches!(
+                    file,
+                    DepotEntry::V1(v1::DepotEntry::File(_))
+                        | DepotEntry::V2(v2::DepotEntry::File(_))
+                ) {
+                    continue;
+                }
+                let root = self.get_file_root(
+                    file.is_support(),
+                    list.is_global_dependency,
+                    &list.product_id,
+                    true,
+                );
+                utils::set_executable(&root.join(file.path()), file.is_executable()).await?;
+            }
+        }
+        for patch in &report.patches {
+            let file = &patch.destination_file;
+            let root = self.get_file_root(file.is_support(), false, &patch.product_id, true);
+            utils::set_executable(&root.join(file.path()), file.is_executable()).await?;
+        }
+
         drop(tx);
         if let Err(err) = progress_report.await {
             log::debug!("Failed to wait for the progress {}", err);
@@ -1250,3 +1596,96 @@ impl Downloader {
         Ok(())
     }
 }
+
+fn lists_size(lists: &[FileList]) -> i64 {
+    lists
+        .iter()
+        .flat_map(|list| list.files.iter())
+        .fold(0, |acc, entry| acc + entry.size())
+}
+
+/// Estimate the update based on the diff of both builds file lists
+pub(crate) async fn estimate_with_diff(
+    reqwest_client: &reqwest::Client,
+    old_manifest: &Manifest,
+    old_build_id: &str,
+    new_manifest: &Manifest,
+    new_build_id: &str,
+    options: &EstimateOptions,
+) -> Result<UpdateEstimate, Error> {
+    let old_language = options.old_language.as_ref().unwrap_or(&options.language);
+    let old_dlcs = options.old_dlcs.as_ref().unwrap_or(&options.dlcs);
+    let bitness = options.bitness.as_ref();
+
+    let depots = new_manifest
+        .get_depots(
+            reqwest_client,
+            &options.language,
+            &options.dlcs,
+            bitness,
+            false,
+        )
+        .await?;
+    let old_depots = old_manifest
+        .get_depots(reqwest_client, old_language, old_dlcs, bitness, false)
+        .await?;
+
+    let re_used_dlcs: Vec<String> = options
+        .dlcs
+        .iter()
+        .filter(|d| old_dlcs.contains(d))
+        .cloned()
+        .collect();
+    let patches = match old_manifest {
+        Manifest::V2(_) => {
+            let filter = super::patches::PatchFilter {
+                dlcs: &re_used_dlcs,
+                new_language: &options.language,
+                old_language,
+                bitness,
+                policy: PatchPolicy::Auto,
+                branch_password: options.branch_password.as_ref(),
+            };
+            super::patches::plan_patches(
+                reqwest_client,
+                new_manifest,
+                new_build_id,
+                old_build_id,
+                &filter,
+                &depots,
+                &old_depots,
+            )
+            .await?
+        }
+        Manifest::V1(_) => None,
+    };
+
+    let disk_delta = lists_size(&depots) - lists_size(&old_depots);
+    let report = diff::diff(
+        depots,
+        old_depots,
+        patches.unwrap_or_default(),
+        PatchPolicy::Auto,
+    );
+
+    let mut estimate = UpdateEstimate {
+        disk_delta,
+        patched: report.patches.len(),
+        full_diff: true,
+        ..Default::default()
+    };
+    for entry in report.download.iter().flat_map(|list| list.files.iter()) {
+        if entry.is_dir() {
+            continue;
+        }
+        estimate.redownloaded += 1;
+        estimate.download_size += entry.compressed_size() as u64;
+    }
+    for patch in &report.patches {
+        for diff in patch.diffs() {
+            estimate.download_size += diff.compressed_size() as u64;
+        }
+    }
+
+    Ok(estimate)
+}
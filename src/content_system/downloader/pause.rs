@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// Shared paused flag of a download  
+/// Workers stop at the next checkpoint while paused, keeping their connections,
+/// permits and partial files until the download is resumed
+#[derive(Clone)]
+pub(crate) struct Pause {
+    paused: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Pause {
+    fn default() -> Self {
+        Self {
+            paused: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl Pause {
+    /// Returns false if the state didn't change
+    pub fn set_paused(&self, paused: bool) -> bool {
+        self.paused.send_if_modified(|state| {
+            let changed = *state != paused;
+            *state = paused;
+            changed
+        })
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Waits until the download is not paused
+    pub async fn wait(&self) {
+        let mut rx = self.paused.subscribe();
+        // The sender is alive as long as self
+        let _ = rx.wait_for(|paused| !paused).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{timeout, Duration};
+
+    #[tokio::test]
+    async fn waits_until_resumed() {
+        let pause = Pause::default();
+        timeout(Duration::from_millis(50), pause.wait())
+            .await
+            .unwrap();
+
+        assert!(pause.set_paused(true));
+        assert!(!pause.set_paused(true));
+        let waiting = tokio::spawn({
+            let pause = pause.clone();
+            async move { pause.wait().await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+
+        assert!(pause.set_paused(false));
+        timeout(Duration::from_millis(50), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!pause.is_paused());
+    }
+}
@@ -1,9 +1,17 @@
 use crate::errors::{io_error, serde_error, EmptyResult};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::{
     fs::{File, OpenOptions},
     io::{AsyncReadExt, AsyncWriteExt},
+    sync::{
+        mpsc::{error::TryRecvError, Sender, UnboundedReceiver},
+        Mutex,
+    },
+    task::JoinHandle,
+    time::{Duration, Instant},
 };
 
 pub(crate) enum DownloadFileStatus {
@@ -28,12 +36,16 @@ impl DownloadFileStatus {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum DownloadState {
     Preparing,
     Allocating(f32),
     Verifying(f32),
     Downloading(DownloadProgress),
+    /// Download was suspended with [`super::Downloader::pause`]
+    Paused,
+    Resumed,
+    Extracting(f32),
     Finished,
 }
 
@@ -43,13 +55,136 @@ pub struct DownloadProgress {
     pub written: u64,
     pub total_download: u64,
     pub total_size: u64,
-    //pub avg_network: f32,
-    //pub avg_disk: f32,
+    /// Bytes downloaded from each endpoint by endpoint name
+    pub served_by: HashMap<String, u64>,
+    /// Number of retried chunk and file transfers
+    pub retries: u64,
+    /// Network throughput in bytes per second over the last few seconds
+    pub avg_network: f32,
+    /// Disk write throughput in bytes per second over the last few seconds
+    pub avg_disk: f32,
+    /// Estimated time left, None until the throughput is known
+    pub eta: Option<Duration>,
+    /// Number of requests in progress
+    pub active_connections: u32,
+    /// Number of files and patches that aren't downloaded yet
+    pub remaining_files: u32,
 }
 
 pub enum WorkerUpdate {
     Download(usize),
     Write(usize),
+    /// Bytes downloaded from the endpoint with given name
+    Served(String, usize),
+    /// Transfer failed and will be retried
+    Retry,
+    /// Downloaded and written bytes that are thrown away and downloaded again
+    Discarded(usize),
+    ConnectionOpened,
+    ConnectionClosed,
+    /// Number of file tasks scheduled
+    FilesQueued(usize),
+    FileFinished,
+}
+
+/// Time span of throughput averages
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Rolling window of progress samples used for throughput and ETA
+#[derive(Default)]
+struct Throughput {
+    /// Time, downloaded and written bytes
+    samples: VecDeque<(Instant, u64, u64)>,
+}
+
+impl Throughput {
+    fn update(&mut self, now: Instant, progress: &mut DownloadProgress) {
+        self.samples
+            .push_back((now, progress.downloaded, progress.written));
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= THROUGHPUT_WINDOW {
+            self.samples.pop_front();
+        }
+        let (start, downloaded, written) = self.samples[0];
+        let elapsed = now.duration_since(start).as_secs_f32();
+        if elapsed == 0.0 {
+            return;
+        }
+        progress.avg_network = progress.downloaded.saturating_sub(downloaded) as f32 / elapsed;
+        progress.avg_disk = progress.written.saturating_sub(written) as f32 / elapsed;
+
+        let remaining = progress.total_download.saturating_sub(progress.downloaded);
+        progress.eta = if remaining == 0 {
+            Some(Duration::ZERO)
+        } else if progress.avg_network > 0.0 {
+            Some(Duration::from_secs_f32(
+                remaining as f32 / progress.avg_network,
+            ))
+        } else {
+            None
+        };
+    }
+}
+
+/// Spawns a task that aggregates worker updates into `download_progress`
+/// and periodically forwards it to the progress channel.  
+/// [`DownloadState::Finished`] is sent once all update senders are dropped
+pub(crate) fn spawn_reporter(
+    download_progress: Arc<Mutex<DownloadProgress>>,
+    mut rx: UnboundedReceiver<WorkerUpdate>,
+    progress_channel_sender: Sender<DownloadState>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut timestamp = Instant::now();
+        let one_sec = Duration::from_secs(1);
+        let mut throughput = Throughput::default();
+        let mut files_queued: usize = 0;
+        let mut files_finished: usize = 0;
+
+        loop {
+            match rx.try_recv() {
+                Ok(message) => {
+                    let mut progress = download_progress.lock().await;
+                    match message {
+                        WorkerUpdate::Download(size) => progress.downloaded += size as u64,
+                        WorkerUpdate::Write(size) => progress.written += size as u64,
+                        WorkerUpdate::Served(endpoint, size) => {
+                            *progress.served_by.entry(endpoint).or_default() += size as u64
+                        }
+                        WorkerUpdate::Retry => progress.retries += 1,
+                        WorkerUpdate::Discarded(size) => {
+                            progress.downloaded = progress.downloaded.saturating_sub(size as u64);
+                            progress.written = progress.written.saturating_sub(size as u64);
+                        }
+                        WorkerUpdate::ConnectionOpened => progress.active_connections += 1,
+                        WorkerUpdate::ConnectionClosed => {
+                            progress.active_connections =
+                                progress.active_connections.saturating_sub(1)
+                        }
+                        WorkerUpdate::FilesQueued(count) => files_queued += count,
+                        WorkerUpdate::FileFinished => files_finished += 1,
+                    }
+                    progress.remaining_files = files_queued.saturating_sub(files_finished) as u32;
+                }
+                Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+            // Report also when idle, so that the throughput drops during stalls
+            if timestamp.elapsed() > Duration::from_millis(500) {
+                timestamp = Instant::now();
+                let mut progress = download_progress.lock().await;
+                throughput.update(timestamp, &mut progress);
+                let _ = progress_channel_sender
+                    .try_send(DownloadState::Downloading((*progress).clone()));
+            }
+        }
+        let progress = download_progress.lock().await;
+        let _ = progress_channel_sender
+            .send_timeout(DownloadState::Downloading((*progress).clone()), one_sec)
+            .await;
+        let _ = progress_channel_sender
+            .send_timeout(DownloadState::Finished, one_sec)
+            .await;
+    })
 }
 
 #[derive(Default, Serialize, Deserialize)]
@@ -94,3 +229,33 @@ pub(crate) async fn write_chunk_state(
     state_file.write_all(&new_buffer).await.map_err(io_error)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_throughput() {
+        let start = Instant::now();
+        let mut throughput = Throughput::default();
+        let mut progress = DownloadProgress {
+            total_download: 10_000,
+            ..Default::default()
+        };
+        throughput.update(start, &mut progress);
+        assert_eq!(progress.eta, None);
+
+        progress.downloaded = 2_000;
+        progress.written = 4_000;
+        throughput.update(start + Duration::from_secs(2), &mut progress);
+        assert_eq!(progress.avg_network, 1_000.0);
+        assert_eq!(progress.avg_disk, 2_000.0);
+        assert_eq!(progress.eta, Some(Duration::from_secs(8)));
+
+        // Samples older than the window are dropped
+        throughput.update(start + Duration::from_secs(8), &mut progress);
+        throughput.update(start + Duration::from_secs(10), &mut progress);
+        assert_eq!(progress.avg_network, 0.0);
+        assert_eq!(progress.eta, None);
+    }
+}
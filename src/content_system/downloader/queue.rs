@@ -0,0 +1,713 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::sync::{broadcast, Notify};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use super::progress::DownloadState;
+use super::{Builder, Downloader, SpeedLimiter};
+use crate::content_system::dependencies::DependenciesManifest;
+use crate::content_system::patches::PatchPolicy;
+use crate::content_system::types::{Manifest, OsBitness};
+use crate::errors::{cancelled_error, io_error, serde_error, task_error, EmptyResult};
+use crate::{Core, Error};
+
+pub type JobId = u64;
+
+/// Runs a started job until it finishes
+type JobRunner = fn(DownloadQueue, JobId, DownloadJob) -> BoxFuture<'static, EmptyResult>;
+
+/// Parameters of a queued download  
+/// The [`Downloader`] is built from them once the job starts, see [`Builder`] for the meaning of the fields
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DownloadJob {
+    pub manifest: Manifest,
+    pub build_id: String,
+    /// Manifest and build id of the installed version when updating
+    pub upgrade_from: Option<(Manifest, String)>,
+    pub install_path: PathBuf,
+    pub support_root: Option<PathBuf>,
+    pub global_dependencies_root: Option<PathBuf>,
+    pub dependency_manifest: Option<DependenciesManifest>,
+    pub language: String,
+    pub old_language: Option<String>,
+    pub dlcs: Vec<String>,
+    pub old_dlcs: Vec<String>,
+    pub verify: bool,
+    pub bitness: Option<OsBitness>,
+    pub patch_policy: PatchPolicy,
+    pub branch_password: Option<String>,
+}
+
+impl DownloadJob {
+    pub fn new(manifest: Manifest, build_id: &str, install_path: PathBuf, language: &str) -> Self {
+        Self {
+            manifest,
+            build_id: build_id.to_owned(),
+            upgrade_from: None,
+            install_path,
+            support_root: None,
+            global_dependencies_root: None,
+            dependency_manifest: None,
+            language: language.to_owned(),
+            old_language: None,
+            dlcs: Vec::new(),
+            old_dlcs: Vec::new(),
+            verify: false,
+            bitness: None,
+            patch_policy: PatchPolicy::default(),
+            branch_password: None,
+        }
+    }
+
+    fn builder(&self, core: Core) -> Builder {
+        let mut builder = Downloader::builder()
+            .core(core)
+            .manifest(self.manifest.clone(), &self.build_id)
+            .install_path(self.install_path.clone())
+            .language(self.language.clone())
+            .dlcs(self.dlcs.clone())
+            .old_dlcs(self.old_dlcs.clone())
+            .patch_policy(self.patch_policy);
+        if let Some((manifest, build_id)) = &self.upgrade_from {
+            builder = builder.upgrade_from(manifest.clone(), build_id);
+        }
+        if let Some(support_root) = &self.support_root {
+            builder = builder.support_root(support_root.clone());
+        }
+        if let Some(root) = &self.global_dependencies_root {
+            builder = builder.global_dependencies_root(root.clone());
+        }
+        if let Some(dependency_manifest) = &self.dependency_manifest {
+            builder = builder.game_dependencies(dependency_manifest.clone());
+        }
+        if let Some(old_language) = &self.old_language {
+            builder = builder.old_language(old_language.clone());
+        }
+        if let Some(bitness) = self.bitness {
+            builder = builder.bitness(bitness);
+        }
+        if let Some(password) = &self.branch_password {
+            builder = builder.branch_password(password.clone());
+        }
+        if self.verify {
+            builder = builder.verify();
+        }
+        builder
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    /// Paused jobs don't start until resumed, also after a restart
+    Paused,
+    /// Job stays in the queue until it's retried or removed
+    Failed,
+}
+
+/// State change of a job
+#[derive(Clone, Debug)]
+pub enum JobState {
+    /// Job was added or moved in the queue
+    Queued,
+    Started,
+    /// Event of the job's [`Downloader`], including pausing and resuming
+    Progress(DownloadState),
+    Finished,
+    Failed(String),
+    /// Job was removed while running
+    Cancelled,
+    /// Job was removed before it started
+    Removed,
+}
+
+#[derive(Clone, Debug)]
+pub struct QueueEvent {
+    pub job: JobId,
+    pub state: JobState,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct QueueEntry {
+    id: JobId,
+    priority: i32,
+    job: DownloadJob,
+    status: JobStatus,
+}
+
+/// Job that was started
+struct RunningJob {
+    cancellation_token: CancellationToken,
+    /// Set by [`DownloadQueue::remove`], other cancellations keep the job in the queue
+    removed: bool,
+    /// Available once the download is prepared
+    downloader: Option<Arc<Downloader>>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct QueueState {
+    next_id: JobId,
+    /// In order of execution among jobs with the same priority
+    entries: Vec<QueueEntry>,
+    #[serde(skip)]
+    running: HashMap<JobId, RunningJob>,
+}
+
+impl QueueState {
+    fn position(&self, id: JobId) -> Option<usize> {
+        self.entries.iter().position(|e| e.id == id)
+    }
+
+    /// Next queued job, highest priority first
+    fn next_queued(&self) -> Option<&QueueEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.status == JobStatus::Queued)
+            .fold(None, |best: Option<&QueueEntry>, entry| match best {
+                Some(best) if best.priority >= entry.priority => Some(best),
+                _ => Some(entry),
+            })
+    }
+}
+
+/// Runs [`Downloader`]s one after another  
+/// Jobs with higher priority start first, jobs with the same priority run in queue order.  
+/// With a state file the queue is saved on every change, interrupted jobs start again
+/// after a restart and continue from the already downloaded data.
+#[derive(Clone)]
+pub struct DownloadQueue {
+    core: Core,
+    state_path: Option<PathBuf>,
+    state: Arc<Mutex<QueueState>>,
+    /// Serializes writes of the state file
+    save_lock: Arc<tokio::sync::Mutex<()>>,
+    max_parallel: Arc<Mutex<usize>>,
+    /// Wakes up the scheduler
+    wake: Arc<Notify>,
+    events: broadcast::Sender<QueueEvent>,
+    speed_limiter: SpeedLimiter,
+    runner: JobRunner,
+}
+
+impl DownloadQueue {
+    /// Creates a queue that is kept only in memory
+    pub fn new(core: Core) -> Self {
+        Self {
+            core,
+            state_path: None,
+            state: Default::default(),
+            save_lock: Default::default(),
+            max_parallel: Arc::new(Mutex::new(1)),
+            wake: Default::default(),
+            events: broadcast::channel(64).0,
+            speed_limiter: SpeedLimiter::default(),
+            runner: |queue, id, job| Box::pin(async move { queue.run_job(id, job).await }),
+        }
+    }
+
+    /// Opens the queue saved at `state_path`, creating an empty one if the file doesn't exist
+    pub async fn open(core: Core, state_path: PathBuf) -> Result<Self, Error> {
+        let mut state: QueueState = match fs::read(&state_path).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(serde_error)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => QueueState::default(),
+            Err(err) => return Err(io_error(err)),
+        };
+        // Jobs interrupted by the restart start again
+        for entry in &mut state.entries {
+            if entry.status == JobStatus::Running {
+                entry.status = JobStatus::Queued;
+            }
+        }
+        let queue = Self::new(core);
+        *queue.state.lock() = state;
+        Ok(Self {
+            state_path: Some(state_path),
+            ..queue
+        })
+    }
+
+    /// Receiver of state changes of every job  
+    /// A receiver that falls behind the progress events gets [`broadcast::error::RecvError::Lagged`]
+    /// and may miss state changes, the current status of queued jobs is available from [`Self::jobs`]
+    pub fn subscribe(&self) -> broadcast::Receiver<QueueEvent> {
+        self.events.subscribe()
+    }
+
+    /// Limiter shared by all jobs of the queue
+    pub fn speed_limiter(&self) -> SpeedLimiter {
+        self.speed_limiter.clone()
+    }
+
+    pub fn max_parallel(&self) -> usize {
+        *self.max_parallel.lock()
+    }
+
+    /// Sets how many jobs run at once, defaults to 1
+    pub fn set_max_parallel(&self, max_parallel: usize) {
+        *self.max_parallel.lock() = max_parallel.max(1);
+        self.wake.notify_one();
+    }
+
+    /// Jobs in the queue in order of execution with their status
+    pub fn jobs(&self) -> Vec<(JobId, JobStatus, DownloadJob)> {
+        let state = self.state.lock();
+        let mut entries: Vec<&QueueEntry> = state.entries.iter().collect();
+        // Started jobs go first, the sort is stable
+        entries.sort_by_key(|e| {
+            (
+                !state.running.contains_key(&e.id),
+                std::cmp::Reverse(e.priority),
+            )
+        });
+        entries
+            .into_iter()
+            .map(|e| (e.id, e.status, e.job.clone()))
+            .collect()
+    }
+
+    /// Adds the job at the end of jobs with the same priority
+    pub async fn enqueue(&self, job: DownloadJob, priority: i32) -> Result<JobId, Error> {
+        let id = {
+            let mut state = self.state.lock();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.entries.push(QueueEntry {
+                id,
+                priority,
+                job,
+                status: JobStatus::Queued,
+            });
+            id
+        };
+        self.changed(id, JobState::Queued).await?;
+        Ok(id)
+    }
+
+    /// Changes the priority of the job, applies to jobs that didn't start yet
+    pub async fn set_priority(&self, id: JobId, priority: i32) -> Result<bool, Error> {
+        let found = {
+            let mut state = self.state.lock();
+            match state.position(id) {
+                Some(position) => {
+                    state.entries[position].priority = priority;
+                    true
+                }
+                None => false,
+            }
+        };
+        if found {
+            self.changed(id, JobState::Queued).await?;
+        }
+        Ok(found)
+    }
+
+    /// Moves the job to `position` in the queue  
+    /// The position is relative to other jobs, priorities still take precedence
+    pub async fn move_to(&self, id: JobId, position: usize) -> Result<bool, Error> {
+        let found = {
+            let mut state = self.state.lock();
+            match state.position(id) {
+                Some(current) => {
+                    let entry = state.entries.remove(current);
+                    let position = position.min(state.entries.len());
+                    state.entries.insert(position, entry);
+                    true
+                }
+                None => false,
+            }
+        };
+        if found {
+            self.changed(id, JobState::Queued).await?;
+        }
+        Ok(found)
+    }
+
+    /// Removes the job from the queue, cancelling it if it's running
+    pub async fn remove(&self, id: JobId) -> Result<bool, Error> {
+        {
+            let mut state = self.state.lock();
+            let Some(position) = state.position(id) else {
+                return Ok(false);
+            };
+            if let Some(running) = state.running.get_mut(&id) {
+                // The scheduler removes the job once it stops
+                running.removed = true;
+                running.cancellation_token.cancel();
+                return Ok(true);
+            }
+            state.entries.remove(position);
+        }
+        self.changed(id, JobState::Removed).await?;
+        Ok(true)
+    }
+
+    /// Pauses the running job, see [`Downloader::pause`]  
+    /// Paused jobs still count towards [`Self::max_parallel`] while they are running
+    pub async fn pause(&self, id: JobId) -> Result<bool, Error> {
+        self.set_paused(id, true).await
+    }
+
+    /// Resumes the paused job, jobs paused before a restart go back to the queue
+    pub async fn resume(&self, id: JobId) -> Result<bool, Error> {
+        self.set_paused(id, false).await
+    }
+
+    async fn set_paused(&self, id: JobId, paused: bool) -> Result<bool, Error> {
+        let downloader = {
+            let mut state = self.state.lock();
+            let Some(position) = state.position(id) else {
+                return Ok(false);
+            };
+            let downloader = match state.running.get(&id) {
+                Some(running) => running.downloader.clone(),
+                None if !paused && state.entries[position].status == JobStatus::Paused => None,
+                None => return Ok(false),
+            };
+            state.entries[position].status = match (paused, state.running.contains_key(&id)) {
+                (true, _) => JobStatus::Paused,
+                (false, true) => JobStatus::Running,
+                (false, false) => JobStatus::Queued,
+            };
+            downloader
+        };
+        // Jobs that are still preparing are paused once the downloader is ready
+        match downloader {
+            Some(downloader) if paused => downloader.pause().await,
+            Some(downloader) => downloader.resume().await,
+            None => {}
+        }
+        self.save().await?;
+        self.wake.notify_one();
+        Ok(true)
+    }
+
+    /// Queues the failed job again
+    pub async fn retry(&self, id: JobId) -> Result<bool, Error> {
+        let found = {
+            let mut state = self.state.lock();
+            match state.position(id) {
+                Some(position) if state.entries[position].status == JobStatus::Failed => {
+                    state.entries[position].status = JobStatus::Queued;
+                    true
+                }
+                _ => false,
+            }
+        };
+        if found {
+            self.changed(id, JobState::Queued).await?;
+        }
+        Ok(found)
+    }
+
+    /// Runs the jobs until cancelled  
+    /// Jobs interrupted by the cancellation stay in the queue, failed jobs stay until they are
+    /// retried or removed
+    pub async fn run(&self, cancellation_token: CancellationToken) -> EmptyResult {
+        let mut tasks: JoinSet<(JobId, Result<(), Error>)> = JoinSet::new();
+        loop {
+            self.start_jobs(&mut tasks, &cancellation_token);
+            tokio::select! {
+                // Jobs stop together with the queue, they must not be finished as removed
+                biased;
+                _ = cancellation_token.cancelled() => {
+                    tasks.shutdown().await;
+                    let mut state = self.state.lock();
+                    state.running.clear();
+                    for entry in &mut state.entries {
+                        if entry.status == JobStatus::Running {
+                            entry.status = JobStatus::Queued;
+                        }
+                    }
+                    return Ok(());
+                }
+                Some(result) = tasks.join_next(), if !tasks.is_empty() => {
+                    let (id, result) = result.map_err(task_error)?;
+                    self.finish_job(id, result).await?;
+                }
+                _ = self.wake.notified() => {}
+            }
+        }
+    }
+
+    fn start_jobs(
+        &self,
+        tasks: &mut JoinSet<(JobId, Result<(), Error>)>,
+        cancellation_token: &CancellationToken,
+    ) {
+        let max_parallel = self.max_parallel();
+        let mut state = self.state.lock();
+        while state.running.len() < max_parallel {
+            let Some(entry) = state.next_queued() else {
+                break;
+            };
+            let (id, job) = (entry.id, entry.job.clone());
+            let position = state.position(id).unwrap();
+            state.entries[position].status = JobStatus::Running;
+            let job_token = cancellation_token.child_token();
+            state.running.insert(
+                id,
+                RunningJob {
+                    cancellation_token: job_token.clone(),
+                    removed: false,
+                    downloader: None,
+                },
+            );
+            log::info!("Starting download job {}", id);
+            let _ = self.events.send(QueueEvent {
+                job: id,
+                state: JobState::Started,
+            });
+            let queue = self.clone();
+            tasks.spawn(async move {
+                let result = tokio::select! {
+                    result = (queue.runner)(queue.clone(), id, job) => result,
+                    _ = job_token.cancelled() => Err(cancelled_error()),
+                };
+                (id, result)
+            });
+        }
+    }
+
+    async fn run_job(&self, id: JobId, job: DownloadJob) -> EmptyResult {
+        let mut downloader = job
+            .builder(self.core.clone())
+            .speed_limiter(self.speed_limiter.clone())
+            .build()?;
+        let mut receiver = downloader.take_progress_receiver().unwrap();
+        let events = self.events.clone();
+        let forwarder = tokio::spawn(async move {
+            while let Some(state) = receiver.recv().await {
+                let _ = events.send(QueueEvent {
+                    job: id,
+                    state: JobState::Progress(state),
+                });
+            }
+        });
+
+        let result = async {
+            downloader.prepare().await?;
+            let downloader = Arc::new(downloader);
+            let paused = {
+                let mut state = self.state.lock();
+                if let Some(running) = state.running.get_mut(&id) {
+                    running.downloader = Some(downloader.clone());
+                }
+                state
+                    .position(id)
+                    .is_some_and(|p| state.entries[p].status == JobStatus::Paused)
+            };
+            if paused {
+                downloader.pause().await;
+            }
+            let result = downloader.download().await;
+            if let Some(running) = self.state.lock().running.get_mut(&id) {
+                running.downloader = None;
+            }
+            result
+        }
+        .await;
+        // The channel closes once the downloader is dropped
+        let _ = forwarder.await;
+        result
+    }
+
+    async fn finish_job(&self, id: JobId, result: EmptyResult) -> EmptyResult {
+        let job_state = {
+            let mut state = self.state.lock();
+            let running = state.running.remove(&id);
+            let removed = running.as_ref().is_some_and(|r| r.removed);
+            let cancelled = running.is_some_and(|r| r.cancellation_token.is_cancelled());
+            let job_state = match result {
+                Ok(()) => JobState::Finished,
+                Err(_) if removed => JobState::Cancelled,
+                // Interrupted without being removed, the job starts again later
+                Err(_) if cancelled => JobState::Queued,
+                Err(err) => {
+                    log::error!("Download job {} failed: {}", id, err);
+                    JobState::Failed(err.to_string())
+                }
+            };
+            // Failed jobs are kept so they can be retried
+            if let Some(position) = state.position(id) {
+                let entry = &mut state.entries[position];
+                match job_state {
+                    JobState::Failed(_) => entry.status = JobStatus::Failed,
+                    JobState::Queued if entry.status == JobStatus::Running => {
+                        entry.status = JobStatus::Queued
+                    }
+                    JobState::Queued => {}
+                    _ => {
+                        state.entries.remove(position);
+                    }
+                }
+            }
+            job_state
+        };
+        self.changed(id, job_state).await
+    }
+
+    /// Saves the queue, notifies the subscribers and the scheduler
+    async fn changed(&self, id: JobId, state: JobState) -> EmptyResult {
+        self.save().await?;
+        let _ = self.events.send(QueueEvent { job: id, state });
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    async fn save(&self) -> EmptyResult {
+        let Some(state_path) = &self.state_path else {
+            return Ok(());
+        };
+        let _guard = self.save_lock.lock().await;
+        let data = serde_json::to_vec(&*self.state.lock()).map_err(serde_error)?;
+        let tmp_path = state_path.with_extension("tmp");
+        fs::write(&tmp_path, data).await.map_err(io_error)?;
+        fs::rename(&tmp_path, state_path).await.map_err(io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(name: &str) -> DownloadJob {
+        let manifest = serde_json::from_value(serde_json::json!({
+            "baseProductId": "1207658924",
+            "buildId": "1",
+            "depots": [],
+            "installDirectory": name,
+            "platform": "windows",
+            "products": [],
+            "version": 2
+        }))
+        .unwrap();
+        DownloadJob::new(manifest, "1", PathBuf::from(name), "en-US")
+    }
+
+    fn order(queue: &DownloadQueue) -> Vec<JobId> {
+        queue.jobs().into_iter().map(|(id, _, _)| id).collect()
+    }
+
+    #[tokio::test]
+    async fn orders_and_persists_jobs() {
+        let root = crate::utils::test_dir("queue");
+        fs::create_dir_all(&root).await.unwrap();
+        let state_path = root.join("queue.json");
+
+        let queue = DownloadQueue::open(Core::new(), state_path.clone())
+            .await
+            .unwrap();
+        let mut events = queue.subscribe();
+        let a = queue.enqueue(job("a"), 0).await.unwrap();
+        let b = queue.enqueue(job("b"), 0).await.unwrap();
+        let c = queue.enqueue(job("c"), 5).await.unwrap();
+        assert_eq!(order(&queue), vec![c, a, b]);
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.job, a);
+        assert!(matches!(event.state, JobState::Queued));
+
+        assert!(queue.move_to(b, 0).await.unwrap());
+        assert_eq!(order(&queue), vec![c, b, a]);
+        assert!(queue.set_priority(a, 10).await.unwrap());
+        assert_eq!(order(&queue), vec![a, c, b]);
+        assert!(queue.remove(c).await.unwrap());
+        assert!(!queue.remove(c).await.unwrap());
+
+        let restored = DownloadQueue::open(Core::new(), state_path.clone())
+            .await
+            .unwrap();
+        assert_eq!(order(&restored), vec![a, b]);
+        let d = restored.enqueue(job("d"), 0).await.unwrap();
+        assert_eq!(d, 3);
+        let (_, status, job) = restored.jobs().remove(0);
+        assert_eq!(status, JobStatus::Queued);
+        assert_eq!(job.install_path, PathBuf::from("a"));
+
+        // Failed and paused jobs keep their status, interrupted ones are queued again
+        let failed = std::io::Error::other("connection reset");
+        restored.finish_job(a, Err(io_error(failed))).await.unwrap();
+        {
+            let mut state = restored.state.lock();
+            let position = state.position(b).unwrap();
+            state.entries[position].status = JobStatus::Paused;
+            let position = state.position(d).unwrap();
+            state.entries[position].status = JobStatus::Running;
+        }
+        restored.save().await.unwrap();
+        let restored = DownloadQueue::open(Core::new(), state_path).await.unwrap();
+        let statuses: Vec<_> = restored
+            .jobs()
+            .into_iter()
+            .map(|(id, status, _)| (id, status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (a, JobStatus::Failed),
+                (b, JobStatus::Paused),
+                (d, JobStatus::Queued)
+            ]
+        );
+        assert!(restored.resume(b).await.unwrap());
+        assert!(restored.retry(a).await.unwrap());
+        assert!(!restored.retry(d).await.unwrap());
+        assert!(restored
+            .jobs()
+            .iter()
+            .all(|(_, status, _)| *status == JobStatus::Queued));
+
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancelling_run_keeps_jobs() {
+        let mut queue = DownloadQueue::new(Core::new());
+        queue.runner = |_, _, _| Box::pin(std::future::pending());
+        let mut events = queue.subscribe();
+        let a = queue.enqueue(job("a"), 0).await.unwrap();
+        let b = queue.enqueue(job("b"), 0).await.unwrap();
+        let c = queue.enqueue(job("c"), 0).await.unwrap();
+
+        let cancellation_token = CancellationToken::new();
+        let run = tokio::spawn({
+            let queue = queue.clone();
+            let cancellation_token = cancellation_token.clone();
+            async move { queue.run(cancellation_token).await }
+        });
+        let mut started = Vec::new();
+        while started.len() < 2 {
+            let event = events.recv().await.unwrap();
+            match event.state {
+                JobState::Started => {
+                    started.push(event.job);
+                    if event.job == a {
+                        assert!(queue.remove(a).await.unwrap());
+                    }
+                }
+                JobState::Cancelled => assert_eq!(event.job, a),
+                _ => {}
+            }
+        }
+        assert_eq!(started, vec![a, b]);
+
+        cancellation_token.cancel();
+        run.await.unwrap().unwrap();
+        let statuses: Vec<_> = queue
+            .jobs()
+            .into_iter()
+            .map(|(id, status, _)| (id, status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![(b, JobStatus::Queued), (c, JobStatus::Queued)]
+        );
+    }
+}
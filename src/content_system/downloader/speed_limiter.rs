@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::time::{Duration, Instant};
+
+struct Bucket {
+    /// Bytes per second, 0 for unlimited
+    rate: u64,
+    /// Available bytes, negative when consumers are waiting
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket limiting the download speed  
+/// Clones share the same limit, so one limiter can cap the speed of multiple [`super::Downloader`]s
+#[derive(Clone)]
+pub struct SpeedLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl Default for SpeedLimiter {
+    fn default() -> Self {
+        Self::new(-1)
+    }
+}
+
+impl SpeedLimiter {
+    /// Creates the limiter with speed in bytes per second, values <= 0 disable the limit
+    pub fn new(max_speed: i32) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                rate: max_speed.max(0) as u64,
+                tokens: max_speed.max(0) as f64,
+                last_refill: Instant::now(),
+            })),
+        }
+    }
+
+    /// Changes the limit, applies to transfers in progress
+    pub fn set_max_speed(&self, max_speed: i32) {
+        let mut bucket = self.bucket.lock();
+        bucket.rate = max_speed.max(0) as u64;
+        bucket.tokens = bucket.tokens.min(bucket.rate as f64);
+        bucket.last_refill = Instant::now();
+    }
+
+    pub fn max_speed(&self) -> i32 {
+        match self.bucket.lock().rate {
+            0 => -1,
+            rate => rate as i32,
+        }
+    }
+
+    /// Waits until `bytes` can be transferred
+    pub async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock();
+            if bucket.rate == 0 {
+                return;
+            }
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            // Allow bursts of up to one second worth of data
+            bucket.tokens = (bucket.tokens + elapsed * bucket.rate as f64).min(bucket.rate as f64);
+            bucket.last_refill = now;
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / bucket.rate as f64)
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn limits_shared_throughput() {
+        let limiter = SpeedLimiter::new(10_000);
+        let start = Instant::now();
+        // First second worth of data goes through immediately
+        limiter.consume(10_000).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        let other = limiter.clone();
+        tokio::join!(limiter.consume(2_000), other.consume(3_000));
+        assert!(start.elapsed() >= Duration::from_millis(450));
+
+        limiter.set_max_speed(-1);
+        assert_eq!(other.max_speed(), -1);
+        let now = Instant::now();
+        other.consume(1_000_000).await;
+        assert!(now.elapsed() < Duration::from_millis(100));
+    }
+}
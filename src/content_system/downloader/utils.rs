@@ -1,3 +1,5 @@
+use std::path::{Path, PathBuf};
+
 use crate::errors::io_error;
 use crate::Error;
 
@@ -40,9 +42,50 @@ pub fn symlink(path: &str, target: &str) -> Result<(), Error> {
 }
 
 #[cfg(not(unix))]
-pub fn symlink(path: &String, target: &String) -> Result<(), Error> {
+pub fn symlink(path: &str, target: &str) -> Result<(), Error> {
     // Symlinks are not available on older versions of Windows, and if they are they require elevated
     // privileges. Thus we ignore any symlinks.
     // In general no one should ever install a depot with symlinks in it on Windows.
     Ok(())
 }
+
+/// Sets or clears the executable bits of the file based on manifest flags  
+/// Executable bit is added wherever the read bit is set
+#[cfg(unix)]
+pub async fn set_executable(path: &Path, executable: bool) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let Ok(metadata) = tokio::fs::symlink_metadata(path).await else {
+        return Ok(());
+    };
+    if !metadata.is_file() {
+        return Ok(());
+    }
+    let mode = metadata.permissions().mode();
+    let new_mode = if executable {
+        mode | ((mode & 0o444) >> 2)
+    } else {
+        mode & !0o111
+    };
+    if new_mode != mode {
+        log::debug!("Changing mode of {} to {:o}", path.display(), new_mode);
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(new_mode))
+            .await
+            .map_err(io_error)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn set_executable(_path: &Path, _executable: bool) -> Result<(), Error> {
+    // There are no permission bits to set
+    Ok(())
+}
+
+/// Path of the diff downloaded for the given step of the patch chain
+pub(crate) fn chain_diff_path(path: &Path, step: usize) -> PathBuf {
+    match step {
+        0 => PathBuf::from(format!("{}.diff", path.display())),
+        step => PathBuf::from(format!("{}.diff.{}", path.display(), step)),
+    }
+}
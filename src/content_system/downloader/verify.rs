@@ -19,7 +19,7 @@ use tokio::{
 
 const READ_CHUNK_SIZE: usize = 1024 * 1024;
 
-async fn calculate_md5(
+pub(crate) async fn calculate_md5(
     file: &mut File,
     offset: i64,
     size: Option<i64>,
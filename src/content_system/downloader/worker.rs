@@ -1,41 +1,65 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::{StreamExt, TryStreamExt};
-use reqwest::Client;
+use md5::{Digest, Md5};
+use reqwest::{Client, Response, StatusCode};
 use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncBufRead;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncSeekExt;
 use tokio::io::AsyncWriteExt;
 use tokio::io::BufReader;
 use tokio::sync::mpsc::UnboundedSender;
-use tokio::sync::OwnedSemaphorePermit;
-use tokio::sync::Semaphore;
-use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tokio::sync::Mutex;
+use tokio_util::io::StreamReader;
 
 use async_compression::tokio::bufread::ZlibDecoder;
 
-use crate::content_system::types::Endpoint;
-use crate::content_system::types::{v1, v2};
+use crate::content_system::types::{v1, v2, Endpoint};
+use crate::errors::checksum_mismatch_error;
 use crate::errors::io_error;
 use crate::errors::request_error;
+use crate::errors::task_error;
 use crate::errors::zlib_error;
 use crate::errors::EmptyResult;
 use crate::utils::{assemble_url, hash_to_galaxy_path};
+use crate::Error;
 
+use super::buffer_pool::{BufferPool, PooledBuffer};
+use super::chunk_cache::ChunkCache;
+use super::chunk_index::{ChunkIndex, ChunkLocation};
+use super::concurrency::{Limit, LimitPermit, ADAPTIVE_MAX_CHUNKS};
+use super::endpoints::PooledEndpoints;
+use super::pause::Pause;
 use super::progress::{load_chunk_state, write_chunk_state, WorkerUpdate};
+use super::speed_limiter::SpeedLimiter;
 
-const BUFFER_SIZE: usize = 256 * 1024;
+/// How many times data with invalid checksum is downloaded before giving up
+const MAX_CHECKSUM_ATTEMPTS: u32 = 3;
+/// How many times a failed chunk or file transfer is retried
+const MAX_RETRIES: u32 = 5;
+
+/// Exponential backoff, same as [`crate::utils::reqwest_exponential_backoff`]
+fn retry_delay(retry: u32) -> Duration {
+    Duration::from_secs_f32(3. * 1.3_f32.powi(retry as i32 - 1))
+}
 
 //TODO: handle downloads gracefully
 
+#[allow(clippy::too_many_arguments)]
 pub async fn v1(
-    _permit: OwnedSemaphorePermit,
+    _permit: LimitPermit,
     reqwest_client: Client,
-    endpoints: Vec<Endpoint>,
+    endpoints: PooledEndpoints,
     entry: v1::DepotEntry,
     destination_path: PathBuf,
     result_report: UnboundedSender<WorkerUpdate>,
+    speed_limiter: SpeedLimiter,
+    pause: Pause,
 ) -> EmptyResult {
     let file = if let v1::DepotEntry::File(f) = entry {
         f
@@ -43,9 +67,6 @@ pub async fn v1(
         return Ok(());
     };
     let download_path = format!("{}.download", destination_path.to_str().unwrap());
-    let endpoint = endpoints.first().unwrap();
-    let url = assemble_url(endpoint, "main.bin");
-
     let Some(offset) = *file.offset() else {
         log::warn!("Offset was not set for v1 file, this shouldn't happen!");
         return Ok(());
@@ -60,20 +81,103 @@ pub async fn v1(
         .await
         .expect("Failed to open the file");
 
-    let response = reqwest_client
-        .get(url)
-        .header("Range", format!("bytes={}-{}", offset, end))
-        .send()
-        .await
-        .map_err(request_error)?;
+    let mut attempt = 1;
+    let mut retries = 0;
+    loop {
+        let mut hasher = Md5::new();
+        let mut written: i64 = 0;
+        let mut endpoint_name = String::new();
+
+        // Interrupted transfers continue from the last written byte
+        while offset + written <= end {
+            pause.wait().await;
+            let result = async {
+                let (response, endpoint) = with_failover(
+                    &reqwest_client,
+                    &endpoints,
+                    "main.bin",
+                    Some((offset + written, end)),
+                    &result_report,
+                    |response| async { Ok(response) },
+                )
+                .await?;
+                endpoint_name.clone_from(endpoint.endpoint_name());
+                let _connection = ConnectionGuard::open(&result_report);
 
-    let mut stream = response.bytes_stream();
+                let mut stream = response.bytes_stream();
+                while let Some(item) = stream.next().await {
+                    let chunk = item.map_err(io_error)?;
+                    pause.wait().await;
+                    speed_limiter.consume(chunk.len()).await;
+                    let _ = result_report.send(WorkerUpdate::Download(chunk.len()));
+                    let _ = result_report.send(WorkerUpdate::Served(
+                        endpoint.endpoint_name().clone(),
+                        chunk.len(),
+                    ));
+                    hasher.update(&chunk);
+                    file_handle.write_all(&chunk).await.map_err(io_error)?;
+                    written += chunk.len() as i64;
+                    let _ = result_report.send(WorkerUpdate::Write(chunk.len()));
+                }
+                // A body that ended early is resumed like an interrupted one
+                if offset + written <= end {
+                    return Err(io_error(std::io::Error::from(
+                        std::io::ErrorKind::UnexpectedEof,
+                    )));
+                }
+                Ok::<_, Error>(())
+            }
+            .await;
+            match result {
+                Ok(()) => break,
+                Err(err) if pause.is_paused() => {
+                    log::debug!(
+                        "Download of {} interrupted while paused: {}",
+                        file.path(),
+                        err
+                    );
+                }
+                Err(err) => {
+                    retries += 1;
+                    if retries > MAX_RETRIES {
+                        return Err(err);
+                    }
+                    log::warn!(
+                        "Download of {} failed at byte {}: {}, retry {}/{}",
+                        file.path(),
+                        written,
+                        err,
+                        retries,
+                        MAX_RETRIES
+                    );
+                    let _ = result_report.send(WorkerUpdate::Retry);
+                    tokio::time::sleep(retry_delay(retries)).await;
+                }
+            }
+        }
 
-    while let Some(item) = stream.next().await {
-        let chunk = item.map_err(io_error)?;
-        let _ = result_report.send(WorkerUpdate::Download(chunk.len()));
-        file_handle.write_all(&chunk).await.map_err(io_error)?;
-        let _ = result_report.send(WorkerUpdate::Write(chunk.len()));
+        // Older manifests may not have the hash
+        if file.hash().is_empty() || format!("{:0x}", hasher.finalize()) == *file.hash() {
+            break;
+        }
+        if attempt == MAX_CHECKSUM_ATTEMPTS {
+            return Err(checksum_mismatch_error(file.path(), None));
+        }
+        log::warn!(
+            "{} from {} has invalid checksum, attempt {}/{}",
+            file.path(),
+            endpoint_name,
+            attempt,
+            MAX_CHECKSUM_ATTEMPTS
+        );
+        attempt += 1;
+        // The file is downloaded again from the start
+        let _ = result_report.send(WorkerUpdate::Discarded(written as usize));
+        file_handle.set_len(0).await.map_err(io_error)?;
+        file_handle
+            .seek(std::io::SeekFrom::Start(0))
+            .await
+            .map_err(io_error)?;
     }
 
     file_handle.flush().await.map_err(io_error)?;
@@ -85,14 +189,355 @@ pub async fn v1(
     Ok(())
 }
 
+/// Reports the request as active until dropped
+struct ConnectionGuard<'a>(&'a UnboundedSender<WorkerUpdate>);
+
+impl<'a> ConnectionGuard<'a> {
+    fn open(result_report: &'a UnboundedSender<WorkerUpdate>) -> Self {
+        let _ = result_report.send(WorkerUpdate::ConnectionOpened);
+        Self(result_report)
+    }
+}
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.0.send(WorkerUpdate::ConnectionClosed);
+    }
+}
+
+/// Requests `path` from the endpoints in order until `read` succeeds for one of them  
+/// Secure links are renewed when they are about to expire or get rejected
+async fn with_failover<T, F, Fut>(
+    reqwest_client: &Client,
+    endpoints: &PooledEndpoints,
+    path: &str,
+    range: Option<(i64, i64)>,
+    result_report: &UnboundedSender<WorkerUpdate>,
+    read: F,
+) -> Result<(T, Endpoint), Error>
+where
+    F: Fn(Response) -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    endpoints.refresh_if_expiring().await;
+    let mut renewed = false;
+    'links: loop {
+        let (ordered, generation) = endpoints.ordered();
+        for (index, endpoint) in ordered.iter().enumerate() {
+            let mut request = reqwest_client.get(assemble_url(endpoint, path));
+            if let Some((start, end)) = range {
+                request = request.header("Range", format!("bytes={}-{}", start, end));
+            }
+            let _connection = ConnectionGuard::open(result_report);
+            let mut mirror_miss = false;
+            let result = match request.send().await {
+                Ok(response) if response.status() == StatusCode::FORBIDDEN && !renewed => {
+                    log::warn!("{} rejected the secure link", endpoint.endpoint_name());
+                    renewed = true;
+                    if endpoints.refresh(generation).await? {
+                        continue 'links;
+                    }
+                    Err(request_error(response.error_for_status().unwrap_err()))
+                }
+                Ok(response) => {
+                    // The mirror may not have every chunk yet, that doesn't make it unhealthy
+                    mirror_miss =
+                        endpoint.is_mirror() && response.status() == StatusCode::NOT_FOUND;
+                    match response.error_for_status() {
+                        Ok(response) => read(response).await,
+                        Err(err) => Err(request_error(err)),
+                    }
+                }
+                Err(err) => Err(request_error(err)),
+            };
+            match result {
+                Ok(data) => {
+                    endpoints.report_success(endpoint);
+                    return Ok((data, endpoint.clone()));
+                }
+                Err(err) => {
+                    if !mirror_miss {
+                        endpoints.report_failure(endpoint);
+                    }
+                    if index + 1 == ordered.len() {
+                        return Err(err);
+                    }
+                    if mirror_miss {
+                        log::debug!("{} is not on the mirror, trying next endpoint", path);
+                        continue;
+                    }
+                    log::warn!(
+                        "Failed to get {} from {}: {}, trying next endpoint",
+                        path,
+                        endpoint.endpoint_name(),
+                        err
+                    );
+                }
+            }
+        }
+        unreachable!("endpoint list is never empty")
+    }
+}
+
+/// Opens the `.download` file for writing at the chunk offset
+async fn open_at(location: &ChunkLocation) -> Result<File, Error> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(&location.download_path)
+        .await
+        .map_err(io_error)?;
+    file.seek(std::io::SeekFrom::Start(location.offset))
+        .await
+        .map_err(io_error)?;
+    Ok(file)
+}
+
+/// Inflates the chunk from `reader` straight into `file` using `buffer`  
+/// Returns md5 and size of the inflated data
+async fn inflate_chunk<R: AsyncBufRead + Unpin>(
+    reader: R,
+    file: &mut File,
+    buffer: &mut [u8],
+) -> Result<(String, usize), Error> {
+    let mut decoder = ZlibDecoder::new(reader);
+    let mut hasher = Md5::new();
+    let mut written = 0;
+    loop {
+        let read = decoder.read(buffer).await.map_err(zlib_error)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read]).await.map_err(io_error)?;
+        written += read;
+    }
+    file.flush().await.map_err(io_error)?;
+    Ok((format!("{:0x}", hasher.finalize()), written))
+}
+
+/// Inflates the chunk from the response into the target file  
+/// With the cache, the compressed data is stored in it as it arrives
+async fn read_chunk(
+    response: Response,
+    target: &ChunkLocation,
+    compressed_md5: &str,
+    buffer: &Mutex<PooledBuffer>,
+    chunk_cache: Option<&ChunkCache>,
+    speed_limiter: &SpeedLimiter,
+    pause: &Pause,
+) -> Result<(String, usize), Error> {
+    let cache_writer = match chunk_cache {
+        Some(chunk_cache) => match chunk_cache.writer(compressed_md5).await {
+            Ok(writer) => Some(Arc::new(Mutex::new(writer))),
+            Err(err) => {
+                log::warn!("Failed to cache chunk {}: {}", compressed_md5, err);
+                None
+            }
+        },
+        None => None,
+    };
+    let speed_limiter = speed_limiter.clone();
+    let pause = pause.clone();
+    let tee = cache_writer.clone();
+    let chunk_data = response
+        .bytes_stream()
+        .then(move |item| {
+            let speed_limiter = speed_limiter.clone();
+            let pause = pause.clone();
+            let tee = tee.clone();
+            async move {
+                if let Ok(bytes) = &item {
+                    pause.wait().await;
+                    speed_limiter.consume(bytes.len()).await;
+                    if let Some(tee) = &tee {
+                        if let Err(err) = tee.lock().await.write(bytes).await {
+                            log::warn!("Failed to cache chunk: {}", err);
+                        }
+                    }
+                }
+                item
+            }
+        })
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
+    let reader = StreamReader::new(Box::pin(chunk_data));
+
+    let mut file = open_at(target).await?;
+    let mut buffer = buffer.lock().await;
+    let result = inflate_chunk(reader, &mut file, &mut buffer).await?;
+
+    // The reader holding the other reference is gone at this point
+    if let Some(Ok(writer)) = cache_writer.map(Arc::try_unwrap) {
+        if let Err(err) = writer.into_inner().commit().await {
+            log::warn!("Failed to cache chunk {}: {}", compressed_md5, err);
+        }
+    }
+    Ok(result)
+}
+
+/// Writes the chunk to `target`, taking it from the cache if available,
+/// otherwise downloading it from the first endpoint that works  
+/// The data is checked against the chunk md5, the download is retried on mismatch
+#[allow(clippy::too_many_arguments)]
+async fn get_chunk(
+    reqwest_client: Client,
+    endpoints: PooledEndpoints,
+    chunk: v2::Chunk,
+    target: ChunkLocation,
+    index: usize,
+    chunk_cache: Option<ChunkCache>,
+    result_report: UnboundedSender<WorkerUpdate>,
+    speed_limiter: SpeedLimiter,
+    pause: Pause,
+    buffers: Arc<BufferPool>,
+) -> Result<ChunkLocation, Error> {
+    let compressed_md5 = chunk.compressed_md5();
+    let path = target.destination_path.display().to_string();
+    let buffer = Mutex::new(buffers.get().await);
+
+    if let Some(chunk_cache) = &chunk_cache {
+        if let Some(cached) = chunk_cache.reader(compressed_md5).await {
+            let mut file = open_at(&target).await?;
+            let mut buffer = buffer.lock().await;
+            match inflate_chunk(BufReader::new(cached), &mut file, &mut buffer).await {
+                Ok((md5, written)) if md5 == *chunk.md5() => {
+                    let _ = result_report.send(WorkerUpdate::Write(written));
+                    return Ok(target);
+                }
+                Ok(_) => log::warn!("Cached chunk {} has invalid content", compressed_md5),
+                Err(err) => log::warn!("Cached chunk {} is unusable: {}", compressed_md5, err),
+            }
+            chunk_cache.remove(compressed_md5).await;
+        }
+    }
+
+    let galaxy_path = hash_to_galaxy_path(compressed_md5);
+    let mut retries = 0;
+    for attempt in 1..=MAX_CHECKSUM_ATTEMPTS {
+        let ((md5, written), endpoint) = loop {
+            let result = with_failover(
+                &reqwest_client,
+                &endpoints,
+                &galaxy_path,
+                None,
+                &result_report,
+                |response| {
+                    read_chunk(
+                        response,
+                        &target,
+                        compressed_md5,
+                        &buffer,
+                        chunk_cache.as_ref(),
+                        &speed_limiter,
+                        &pause,
+                    )
+                },
+            )
+            .await;
+            match result {
+                Ok(result) => break result,
+                Err(err) if pause.is_paused() => {
+                    log::debug!(
+                        "Download of chunk {} of {} interrupted while paused: {}",
+                        index,
+                        path,
+                        err
+                    );
+                    pause.wait().await;
+                }
+                Err(err) => {
+                    retries += 1;
+                    if retries > MAX_RETRIES {
+                        return Err(err);
+                    }
+                    log::warn!(
+                        "Download of chunk {} of {} failed: {}, retry {}/{}",
+                        index,
+                        path,
+                        err,
+                        retries,
+                        MAX_RETRIES
+                    );
+                    let _ = result_report.send(WorkerUpdate::Retry);
+                    tokio::time::sleep(retry_delay(retries)).await;
+                }
+            }
+        };
+        let _ = result_report.send(WorkerUpdate::Served(
+            endpoint.endpoint_name().clone(),
+            *chunk.compressed_size() as usize,
+        ));
+        if md5 == *chunk.md5() {
+            let _ = result_report.send(WorkerUpdate::Write(written));
+            return Ok(target);
+        }
+        log::warn!(
+            "Chunk {} of {} from {} has invalid checksum, attempt {}/{}",
+            index,
+            path,
+            endpoint.endpoint_name(),
+            attempt,
+            MAX_CHECKSUM_ATTEMPTS
+        );
+    }
+    Err(checksum_mismatch_error(&path, Some(index)))
+}
+
+/// Copies a shared chunk that was downloaded for another file  
+/// Returns false if the copy doesn't match the chunk md5
+async fn copy_chunk(
+    source: &ChunkLocation,
+    target: &ChunkLocation,
+    chunk: &v2::Chunk,
+    buffers: &Arc<BufferPool>,
+    result_report: &UnboundedSender<WorkerUpdate>,
+) -> Result<bool, Error> {
+    // The other file may be already complete
+    let mut source_file = match File::open(&source.download_path).await {
+        Ok(file) => file,
+        Err(_) => File::open(&source.destination_path)
+            .await
+            .map_err(io_error)?,
+    };
+    source_file
+        .seek(std::io::SeekFrom::Start(source.offset))
+        .await
+        .map_err(io_error)?;
+    let mut file = open_at(target).await?;
+    let mut buffer = buffers.get().await;
+    let mut hasher = Md5::new();
+    let mut remaining = *chunk.size() as usize;
+    while remaining > 0 {
+        let size = remaining.min(buffer.len());
+        source_file
+            .read_exact(&mut buffer[..size])
+            .await
+            .map_err(io_error)?;
+        hasher.update(&buffer[..size]);
+        file.write_all(&buffer[..size]).await.map_err(io_error)?;
+        remaining -= size;
+    }
+    file.flush().await.map_err(io_error)?;
+    if format!("{:0x}", hasher.finalize()) != *chunk.md5() {
+        return Ok(false);
+    }
+    let _ = result_report.send(WorkerUpdate::Write(*chunk.size() as usize));
+    Ok(true)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn v2(
-    _permit: OwnedSemaphorePermit,
+    _permit: LimitPermit,
     reqwest_client: Client,
-    chunk_semaphore: Arc<Semaphore>,
-    endpoints: Vec<Endpoint>,
+    chunk_limit: Arc<Limit>,
+    buffers: Arc<BufferPool>,
+    endpoints: PooledEndpoints,
     entry: v2::DepotEntry,
     destination_path: PathBuf,
     result_report: UnboundedSender<WorkerUpdate>,
+    chunk_index: Option<Arc<ChunkIndex>>,
+    chunk_cache: Option<ChunkCache>,
+    speed_limiter: SpeedLimiter,
+    pause: Pause,
 ) -> EmptyResult {
     let chunks = match &entry {
         v2::DepotEntry::File(file) => file.chunks.clone(),
@@ -103,7 +548,8 @@ pub async fn v2(
     let download_path = format!("{}.download", destination_path.to_str().unwrap());
     let state_path = format!("{}.state", destination_path.to_str().unwrap());
 
-    let mut file_handle = OpenOptions::new()
+    // Chunks are written through their own handles
+    OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(false)
@@ -124,7 +570,6 @@ pub async fn v2(
         None
     };
 
-    let endpoint = endpoints.first().unwrap();
     let mut handles: Vec<_> = Vec::new();
 
     let mut state = load_chunk_state(&state_path).await.unwrap_or_default();
@@ -134,57 +579,88 @@ pub async fn v2(
     let mut offset: i64 = 0;
     for (index, chunk) in chunks.into_iter().enumerate() {
         let reqwest_client = reqwest_client.clone();
-        let chunk_semaphore = chunk_semaphore.clone();
-        let chunk_offset = offset;
+        let chunk_limit = chunk_limit.clone();
+        let target = ChunkLocation {
+            download_path: PathBuf::from(&download_path),
+            destination_path: destination_path.clone(),
+            offset: offset as u64,
+        };
         offset += chunk.size();
         if *state.chunks.get(index).unwrap_or(&false) {
             continue;
         }
         let result_report = result_report.clone();
+        let chunk_index = chunk_index.clone();
+        let chunk_cache = chunk_cache.clone();
+        let endpoints = endpoints.clone();
+        let speed_limiter = speed_limiter.clone();
+        let pause = pause.clone();
+        let buffers = buffers.clone();
         let chunk_handle = async move {
-            let _permit = chunk_semaphore.acquire().await.unwrap();
-            let galaxy_path = hash_to_galaxy_path(chunk.compressed_md5());
-            let url = assemble_url(endpoint, &galaxy_path);
-
-            tokio::spawn(async move {
-                let response = reqwest_client
-                    .get(url)
-                    .send()
-                    .await
-                    .map_err(request_error)?;
-
-                let chunk_data = response.bytes_stream();
-                let chunk_data = chunk_data
-                    .map_err(|e| futures::io::Error::new(futures::io::ErrorKind::Other, e))
-                    .into_async_read();
-                let reader = BufReader::with_capacity(BUFFER_SIZE, chunk_data.compat());
-                let mut decompressed_data = ZlibDecoder::new(reader);
-                let mut buffer = Vec::with_capacity((*chunk.size()).try_into().unwrap());
-                decompressed_data
-                    .read_to_end(&mut buffer)
-                    .await
-                    .map_err(zlib_error)?;
+            let _permit = chunk_limit.acquire().await;
+            pause.wait().await;
+            let fetch = || {
+                let handle = tokio::spawn(get_chunk(
+                    reqwest_client.clone(),
+                    endpoints.clone(),
+                    chunk.clone(),
+                    target.clone(),
+                    index,
+                    chunk_cache.clone(),
+                    result_report.clone(),
+                    speed_limiter.clone(),
+                    pause.clone(),
+                    buffers.clone(),
+                ));
+                async move { handle.await.map_err(task_error)? }
+            };
+
+            // Chunks used by multiple files are downloaded only once
+            let fetched = match &chunk_index {
+                Some(chunk_index) if chunk_index.is_shared(chunk.compressed_md5()) => {
+                    let (location, fetched) = chunk_index
+                        .get_or_fetch(chunk.compressed_md5(), fetch)
+                        .await?;
+                    if fetched {
+                        true
+                    } else {
+                        match copy_chunk(&location, &target, &chunk, &buffers, &result_report).await
+                        {
+                            Ok(true) => false,
+                            result => {
+                                log::warn!(
+                                    "Failed to copy shared chunk {} to {}: {:?}",
+                                    chunk.compressed_md5(),
+                                    target.destination_path.display(),
+                                    result.err()
+                                );
+                                fetch().await?;
+                                true
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    fetch().await?;
+                    true
+                }
+            };
 
+            if fetched {
                 let _ =
                     result_report.send(WorkerUpdate::Download(*chunk.compressed_size() as usize));
-                Ok((buffer, index, chunk_offset))
-            })
-            .await
-            .unwrap()
+            }
+            Ok::<_, Error>(index)
         };
         handles.push(chunk_handle)
     }
 
-    let mut stream = futures::stream::iter(handles).buffer_unordered(6);
+    // Chunks wait for the permits, so that raising the limit applies to the file in progress
+    let buffer = chunk_limit.get().max(ADAPTIVE_MAX_CHUNKS);
+    let mut stream = futures::stream::iter(handles).buffer_unordered(buffer);
 
-    while let Some(chunk) = stream.next().await {
-        let (chunk, index, offset) = chunk?;
-        file_handle
-            .seek(std::io::SeekFrom::Start(offset.try_into().unwrap()))
-            .await
-            .map_err(io_error)?;
-        file_handle.write_all(&chunk).await.map_err(io_error)?;
-        let _ = result_report.send(WorkerUpdate::Write(chunk.len()));
+    while let Some(index) = stream.next().await {
+        let index = index?;
         *state.chunks.get_mut(index).unwrap() = true;
         if let Some(state_file) = &mut state_file {
             write_chunk_state(state_file, &state)
@@ -197,8 +673,6 @@ pub async fn v2(
             state_file.flush().await.map_err(io_error)?;
         }
     }
-    file_handle.flush().await.map_err(io_error)?;
-    drop(file_handle);
     drop(state_file);
 
     tokio::fs::rename(download_path, destination_path)
@@ -207,3 +681,249 @@ pub async fn v2(
     let _ = tokio::fs::remove_file(state_path).await.map_err(io_error);
     Ok(())
 }
+
+/// Copies chunks that didn't change since the installed version of the file
+/// into the `.download` file and marks them as done in the chunk state,
+/// so that [`v2`] downloads only the missing ones
+pub async fn copy_local_chunks(
+    source_path: &Path,
+    old_chunks: &[v2::Chunk],
+    entry: &v2::DepotEntry,
+    destination_path: &Path,
+    buffers: &Arc<BufferPool>,
+    result_report: &UnboundedSender<WorkerUpdate>,
+) -> EmptyResult {
+    let v2::DepotEntry::File(file) = entry else {
+        return Ok(());
+    };
+    // Single chunk files don't use chunk state
+    if file.chunks.len() < 2 {
+        return Ok(());
+    }
+    let mut source = match OpenOptions::new().read(true).open(source_path).await {
+        Ok(source) => source,
+        Err(err) => {
+            log::debug!("Can't reuse chunks of {}: {}", source_path.display(), err);
+            return Ok(());
+        }
+    };
+
+    let mut old_offsets: HashMap<&String, (u64, i64)> = HashMap::new();
+    let mut offset: u64 = 0;
+    for chunk in old_chunks {
+        old_offsets
+            .entry(chunk.md5())
+            .or_insert((offset, *chunk.size()));
+        offset += *chunk.size() as u64;
+    }
+
+    let download_path = format!("{}.download", destination_path.to_str().unwrap());
+    let state_path = format!("{}.state", destination_path.to_str().unwrap());
+
+    let mut state = load_chunk_state(&state_path).await.unwrap_or_default();
+    state.header.number_of_chunks = file.chunks.len() as u32;
+    state.chunks.resize(file.chunks.len(), false);
+
+    let mut file_handle = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&download_path)
+        .await
+        .map_err(io_error)?;
+
+    let mut buffer = buffers.get().await;
+    let mut reused = 0;
+    let mut offset: u64 = 0;
+    for (index, chunk) in file.chunks.iter().enumerate() {
+        let chunk_offset = offset;
+        offset += *chunk.size() as u64;
+        if state.chunks[index] {
+            continue;
+        }
+        let Some((source_offset, size)) = old_offsets.get(chunk.md5()) else {
+            continue;
+        };
+        if size != chunk.size() {
+            continue;
+        }
+
+        source
+            .seek(std::io::SeekFrom::Start(*source_offset))
+            .await
+            .map_err(io_error)?;
+        file_handle
+            .seek(std::io::SeekFrom::Start(chunk_offset))
+            .await
+            .map_err(io_error)?;
+        let mut hasher = Md5::new();
+        let mut remaining = *size as usize;
+        while remaining > 0 {
+            let read_size = remaining.min(buffer.len());
+            if source.read_exact(&mut buffer[..read_size]).await.is_err() {
+                break;
+            }
+            hasher.update(&buffer[..read_size]);
+            file_handle
+                .write_all(&buffer[..read_size])
+                .await
+                .map_err(io_error)?;
+            remaining -= read_size;
+        }
+        // The installed file may have been modified, the chunk is downloaded over the copy then
+        if remaining > 0 || format!("{:0x}", hasher.finalize()) != *chunk.md5() {
+            continue;
+        }
+
+        let _ = result_report.send(WorkerUpdate::Download(*chunk.compressed_size() as usize));
+        let _ = result_report.send(WorkerUpdate::Write(*size as usize));
+        state.chunks[index] = true;
+        reused += 1;
+    }
+    file_handle.flush().await.map_err(io_error)?;
+
+    if reused > 0 {
+        log::debug!(
+            "Reused {}/{} chunks of {}",
+            reused,
+            file.chunks.len(),
+            file.path
+        );
+        let mut state_file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&state_path)
+            .await
+            .map_err(io_error)?;
+        write_chunk_state(&mut state_file, &state).await?;
+        state_file.flush().await.map_err(io_error)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::buffer_pool::BUFFER_SIZE;
+    use super::*;
+
+    fn chunk(data: &[u8]) -> serde_json::Value {
+        serde_json::json!({
+            "compressedMd5": "c",
+            "md5": format!("{:0x}", Md5::digest(data)),
+            "size": data.len(),
+            "compressedSize": data.len()
+        })
+    }
+
+    #[tokio::test]
+    async fn reuse_unchanged_chunks() {
+        let root = crate::utils::test_dir("reuse-chunks");
+        tokio::fs::create_dir_all(&root).await.unwrap();
+        let source_path = root.join("installed.bin");
+        tokio::fs::write(&source_path, b"aaaabbbb").await.unwrap();
+
+        let old_chunks: Vec<v2::Chunk> =
+            serde_json::from_value(serde_json::json!([chunk(b"aaaa"), chunk(b"bbbb")])).unwrap();
+        let entry: v2::DepotEntry = serde_json::from_value(serde_json::json!({
+            "type": "DepotFile",
+            "path": "installed.bin",
+            "chunks": [chunk(b"cccc"), chunk(b"bbbb")]
+        }))
+        .unwrap();
+
+        let destination_path = root.join("new.bin");
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let buffers = BufferPool::new(BUFFER_SIZE as u64);
+        copy_local_chunks(
+            &source_path,
+            &old_chunks,
+            &entry,
+            &destination_path,
+            &buffers,
+            &tx,
+        )
+        .await
+        .unwrap();
+
+        let state_path = format!("{}.state", destination_path.display());
+        let state = load_chunk_state(&state_path).await.unwrap();
+        assert_eq!(state.chunks, vec![false, true]);
+        let data = tokio::fs::read(format!("{}.download", destination_path.display()))
+            .await
+            .unwrap();
+        assert_eq!(&data[4..], b"bbbb");
+        assert!(matches!(rx.try_recv(), Ok(WorkerUpdate::Download(4))));
+
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn streams_chunks_to_offset() {
+        let root = crate::utils::test_dir("stream-chunks");
+        tokio::fs::create_dir_all(&root).await.unwrap();
+
+        // Bigger than a single buffer
+        let data: Vec<u8> = (0..BUFFER_SIZE * 2 + 10).map(|i| (i % 251) as u8).collect();
+        let mut compressed = Vec::new();
+        async_compression::tokio::bufread::ZlibEncoder::new(&data[..])
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+        let compressed_md5 = format!("{:0x}", Md5::digest(&compressed));
+        let cache = ChunkCache::open(root.join("cache"), None).await.unwrap();
+        cache.insert(&compressed_md5, &compressed).await.unwrap();
+        let chunk: v2::Chunk = serde_json::from_value(serde_json::json!({
+            "compressedMd5": compressed_md5,
+            "md5": format!("{:0x}", Md5::digest(&data)),
+            "size": data.len(),
+            "compressedSize": compressed.len()
+        }))
+        .unwrap();
+
+        let location = |name: &str| ChunkLocation {
+            download_path: root.join(format!("{}.download", name)),
+            destination_path: root.join(name),
+            offset: 3,
+        };
+        let (first, second) = (location("first"), location("second"));
+        tokio::fs::write(&first.download_path, b"abc")
+            .await
+            .unwrap();
+        tokio::fs::write(&second.download_path, b"xyz")
+            .await
+            .unwrap();
+
+        // A single buffer is enough for any chunk
+        let buffers = BufferPool::new(0);
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let endpoints =
+            Arc::new(super::super::endpoints::EndpointPool::default()).endpoints(Vec::new(), None);
+        let written = get_chunk(
+            Client::new(),
+            endpoints,
+            chunk.clone(),
+            first.clone(),
+            0,
+            Some(cache),
+            tx.clone(),
+            SpeedLimiter::default(),
+            Pause::default(),
+            buffers.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, first);
+        assert!(copy_chunk(&first, &second, &chunk, &buffers, &tx)
+            .await
+            .unwrap());
+
+        for (location, prefix) in [(first, b"abc"), (second, b"xyz")] {
+            let file = tokio::fs::read(&location.download_path).await.unwrap();
+            assert_eq!(&file[..3], prefix);
+            assert_eq!(&file[3..], &data[..]);
+        }
+
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+}
@@ -523,6 +523,20 @@ pub fn get_language(query: &str) -> Option<&Language<'static>> {
         })
 }
 
+fn normalize_language(lang: &str) -> String {
+    match get_language(lang) {
+        Some(lang) => lang.code,
+        None => {
+            if lang.to_lowercase() == "neutral" {
+                "*"
+            } else {
+                lang
+            }
+        }
+    }
+    .to_string()
+}
+
 pub(crate) fn serde_language<'de, D>(d: D) -> Result<Vec<String>, D::Error>
 where
     D: Deserializer<'de>,
@@ -530,18 +544,14 @@ where
     let languages: Vec<String> = Vec::deserialize(d)?;
     Ok(languages
         .iter()
-        .map(|lang| {
-            match get_language(lang) {
-                Some(lang) => lang.code,
-                None => {
-                    if lang.to_lowercase() == "neutral" {
-                        "*"
-                    } else {
-                        lang
-                    }
-                }
-            }
-            .to_string()
-        })
+        .map(|lang| normalize_language(lang))
         .collect())
 }
+
+pub(crate) fn serde_single_language<'de, D>(d: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let language = String::deserialize(d)?;
+    Ok(normalize_language(&language))
+}
@@ -0,0 +1,220 @@
+use std::path::PathBuf;
+
+use reqwest::Client;
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+use super::downloader::ChunkCache;
+use super::types::{Endpoint, Manifest};
+use crate::constants::domains::GOG_CDN;
+use crate::errors::{io_error, request_error};
+use crate::utils::{hash_to_galaxy_path, reqwest_exponential_backoff};
+use crate::Error;
+
+/// Maximum size of request line and headers
+const MAX_REQUEST_SIZE: u64 = 8 * 1024;
+
+const CONTENT_SYSTEM_PREFIX: &str = "/content-system/v2/";
+
+/// Minimal HTTP server sharing downloaded content on the local network  
+/// It serves the chunks of a [`ChunkCache`] and stored depot manifests under the
+/// same paths the GOG CDN uses, so that [`Endpoint::mirror`] can be used in place of a CDN endpoint
+#[derive(Clone)]
+pub struct MirrorServer {
+    chunk_cache: ChunkCache,
+    /// Directory with depot manifests, laid out like `/content-system/v2/`
+    meta_root: PathBuf,
+}
+
+impl MirrorServer {
+    pub fn new(chunk_cache: ChunkCache, meta_root: PathBuf) -> Self {
+        Self {
+            chunk_cache,
+            meta_root,
+        }
+    }
+
+    /// Endpoint for the clients, see [`Endpoint::mirror`]
+    pub fn endpoint(base_url: &str, store_path: &str) -> Endpoint {
+        Endpoint::mirror(base_url, store_path)
+    }
+
+    /// Downloads the depot manifests of the build, making them available to the clients
+    pub async fn store_depot_meta(
+        &self,
+        reqwest_client: &Client,
+        manifest: &Manifest,
+    ) -> Result<(), Error> {
+        let Manifest::V2(manifest) = manifest else {
+            return Ok(());
+        };
+        for depot in manifest.depots().iter().chain(manifest.offline_depot()) {
+            let path = format!("meta/{}", hash_to_galaxy_path(depot.manifest()));
+            let file_path = self.meta_root.join(&path);
+            if file_path.exists() {
+                continue;
+            }
+            let url = format!("{}{}{}", GOG_CDN, CONTENT_SYSTEM_PREFIX, path);
+            let response = reqwest_exponential_backoff(reqwest_client.get(url))
+                .await
+                .map_err(request_error)?
+                .error_for_status()
+                .map_err(request_error)?;
+            let data = response.bytes().await.map_err(request_error)?;
+            fs::create_dir_all(file_path.parent().unwrap())
+                .await
+                .map_err(io_error)?;
+            fs::write(&file_path, data).await.map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    /// Accepts connections until cancelled
+    pub async fn serve(
+        &self,
+        listener: TcpListener,
+        cancellation_token: CancellationToken,
+    ) -> Result<(), Error> {
+        loop {
+            let (stream, address) = tokio::select! {
+                result = listener.accept() => result.map_err(io_error)?,
+                _ = cancellation_token.cancelled() => return Ok(()),
+            };
+            log::debug!("Mirror connection from {}", address);
+            let server = self.clone();
+            let cancellation_token = cancellation_token.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    result = server.handle_connection(stream) => {
+                        if let Err(err) = result {
+                            log::debug!("Mirror connection from {} failed: {}", address, err);
+                        }
+                    }
+                    _ = cancellation_token.cancelled() => {}
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) -> std::io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        loop {
+            let mut head = String::new();
+            let mut limited = (&mut reader).take(MAX_REQUEST_SIZE);
+            loop {
+                let read = limited.read_line(&mut head).await?;
+                if read == 0 {
+                    // Connection closed or request too big
+                    return Ok(());
+                }
+                if head.ends_with("\r\n\r\n") || head == "\r\n" {
+                    break;
+                }
+            }
+            let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+            let method = request_line.next().unwrap_or_default();
+            let path = request_line.next().unwrap_or_default();
+            let keep_alive = !head
+                .lines()
+                .any(|l| l.eq_ignore_ascii_case("connection: close"));
+
+            let (status, body) = match method {
+                "GET" | "HEAD" => match self.resolve(path).await {
+                    Some(data) => ("200 OK", data),
+                    None => ("404 Not Found", Vec::new()),
+                },
+                _ => ("405 Method Not Allowed", Vec::new()),
+            };
+            let response_head = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Type: application/octet-stream\r\nConnection: {}\r\n\r\n",
+                status,
+                body.len(),
+                if keep_alive { "keep-alive" } else { "close" }
+            );
+            writer.write_all(response_head.as_bytes()).await?;
+            if method != "HEAD" {
+                writer.write_all(&body).await?;
+            }
+            writer.flush().await?;
+            if !keep_alive {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns the content for the request path
+    async fn resolve(&self, path: &str) -> Option<Vec<u8>> {
+        let path = path.split('?').next()?;
+        let path = path.strip_prefix(CONTENT_SYSTEM_PREFIX)?;
+        let segments: Vec<&str> = path.split('/').collect();
+        // Every item is stored under ab/cd/abcd...
+        let [.., first, second, hash] = segments[..] else {
+            return None;
+        };
+        if hash.len() != 32
+            || !hash.chars().all(|c| c.is_ascii_hexdigit())
+            || format!("{}/{}/{}", first, second, hash) != hash_to_galaxy_path(hash)
+        {
+            return None;
+        }
+
+        match segments[..segments.len() - 3] {
+            // store/{product_id}, patches/store/{product_id}, dependencies/store
+            [.., "store", _] | ["dependencies", "store"] => self.chunk_cache.get(hash).await,
+            ["meta"] | ["patches", "meta"] | ["dependencies", "meta"] => {
+                fs::read(self.meta_root.join(path)).await.ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::assemble_url;
+    use md5::{Digest, Md5};
+
+    #[tokio::test]
+    async fn serves_cached_chunks() {
+        let root = crate::utils::test_dir("mirror");
+        let cache = ChunkCache::open(root.join("store"), None).await.unwrap();
+        let data = b"chunk data";
+        let md5 = format!("{:0x}", Md5::digest(data));
+        cache.insert(&md5, data).await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = MirrorServer::new(cache, root.join("meta"));
+        let cancellation_token = CancellationToken::new();
+        let server_token = cancellation_token.clone();
+        let handle = tokio::spawn(async move { server.serve(listener, server_token).await });
+
+        let endpoint = MirrorServer::endpoint(
+            &format!("http://{}/", address),
+            "/content-system/v2/store/1207658924",
+        );
+        let client = Client::builder().no_proxy().build().unwrap();
+        let url = assemble_url(&endpoint, &hash_to_galaxy_path(&md5));
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(&response.bytes().await.unwrap()[..], data);
+
+        let missing = assemble_url(&endpoint, &hash_to_galaxy_path(&"0".repeat(32)));
+        let response = client.get(missing).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+        let response = client
+            .get(format!("http://{}/content-system/v2/../secret", address))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+
+        cancellation_token.cancel();
+        handle.await.unwrap().unwrap();
+        fs::remove_dir_all(&root).await.unwrap();
+    }
+}
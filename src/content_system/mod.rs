@@ -9,11 +9,14 @@ pub mod dependencies;
 #[cfg(feature = "downloader")]
 pub mod downloader;
 pub mod languages;
+#[cfg(feature = "mirror")]
+pub mod mirror;
 pub mod patches;
 pub mod secure_link;
 #[cfg(test)]
 mod tests;
 pub mod types;
+pub mod updates;
 
 pub(crate) async fn get_builds(
     client: &Client,
@@ -1,16 +1,25 @@
 use async_compression::tokio::bufread::ZlibDecoder;
 use derive_getters::Getters;
+use futures::StreamExt;
 use reqwest::Client;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::io::AsyncReadExt;
 use url::Url;
 
+use std::collections::{HashMap, HashSet};
+
 use crate::constants::domains::{GOG_CDN, GOG_CONTENT_SYSTEM};
 use crate::errors::{request_error, serde_error, zlib_error};
 use crate::utils::reqwest_exponential_backoff;
 
-use super::types::v2::{DepotDetails, ManifestDepot};
-use super::types::{DepotEntry, FileList, Manifest};
+use super::types::traits::EntryUtils;
+use super::types::v2::{self, DepotDetails, ManifestDepot};
+use super::types::{v1, DepotEntry, FileList, Manifest, OsBitness, Platform};
+
+/// Maximum number of builds between the installed and target build considered for patch chains
+const MAX_INTERMEDIATE_BUILDS: usize = 8;
+/// Number of patches between intermediate builds fetched at once
+const HOP_FETCH_CONCURRENCY: usize = 4;
 
 #[derive(Deserialize, Getters, Debug)]
 pub struct PatchIndex {
@@ -42,48 +51,45 @@ pub struct PatchDepots {
     depots: Vec<ManifestDepot>,
 }
 
-pub async fn get_patches(
-    reqwest_client: &Client,
-    manifest: &Option<Manifest>,
-    build_id: &Option<String>,
-    old_manifest: &Option<Manifest>,
-    old_build_id: Option<String>,
-    dlcs: Vec<String>,
-    new_language: &String,
-    old_language: &String,
-) -> Result<Option<Vec<FileList>>, crate::Error> {
-    if manifest.is_none() || build_id.is_none() {
-        return Ok(None);
-    }
-    if old_manifest.is_none() || old_build_id.is_none() {
-        return Ok(None);
-    }
-    if let Some(Manifest::V1(_)) = manifest {
-        return Ok(None);
-    }
-    if let Some(Manifest::V1(_)) = old_manifest {
-        return Ok(None);
+impl PatchDepots {
+    /// Returns patch depots for the base product and dlcs, matching both of the languages
+    pub fn wanted_depots(
+        &self,
+        product_id: &String,
+        dlcs: &[String],
+        new_language: &String,
+        old_language: &String,
+        bitness: Option<&OsBitness>,
+    ) -> Vec<&ManifestDepot> {
+        self.depots
+            .iter()
+            .filter(|d| {
+                // Check if product matches root or one of previously installed dlcs
+                (d.product_id() == product_id || dlcs.contains(d.product_id()))
+                    && (d.languages().iter().any(|l| l == "*") // Check if depot is for all languages
+                        || (d.languages().contains(old_language) // Or check if both 
+                            && d.languages().contains(new_language))) // languages match the depot
+                    && d.matches_bitness(bitness)
+            })
+            .collect()
     }
+}
 
-    if build_id == &old_build_id {
-        return Ok(None);
-    }
-
-    let build_id = build_id.clone().unwrap();
-
-    let product_id = if let Some(manifest) = manifest {
-        manifest.product_id()
-    } else {
-        return Ok(None);
-    };
-
+/// Fetches the patch between two builds  
+/// Returns None if there is no patch available
+pub async fn get_patch_depots(
+    reqwest_client: &Client,
+    product_id: &str,
+    from_build_id: &str,
+    to_build_id: &str,
+) -> Result<Option<PatchDepots>, crate::Error> {
     let index_url = format!("{}/products/{}/patches", GOG_CONTENT_SYSTEM, product_id);
     let index_url = Url::parse_with_params(
         &index_url,
         [
             ("_version", "4"),
-            ("from_build_id", &old_build_id.unwrap()),
-            ("to_build_id", &build_id),
+            ("from_build_id", from_build_id),
+            ("to_build_id", to_build_id),
         ],
     )
     .unwrap();
@@ -116,18 +122,69 @@ pub async fn get_patches(
         return Ok(None);
     }
 
-    let wanted_depots: Vec<&ManifestDepot> = depots
-        .depots()
-        .iter()
-        .filter(|d| {
-            // Check if product matches root or one of previously installed dlcs
-            (d.product_id() == &product_id || dlcs.contains(d.product_id()))
-                && (d.languages().iter().any(|l| l == "*") // Check if depot is for all languages
-                    || (d.languages().contains(old_language) // Or check if both 
-                        && d.languages().contains(new_language))) // languages match the depot
-        })
-        .collect();
+    Ok(Some(depots))
+}
 
+pub async fn get_patches(
+    reqwest_client: &Client,
+    manifest: &Option<Manifest>,
+    build_id: &Option<String>,
+    old_manifest: &Option<Manifest>,
+    old_build_id: Option<String>,
+    dlcs: Vec<String>,
+    new_language: &String,
+    old_language: &String,
+    bitness: Option<&OsBitness>,
+) -> Result<Option<Vec<FileList>>, crate::Error> {
+    if manifest.is_none() || build_id.is_none() {
+        return Ok(None);
+    }
+    if old_manifest.is_none() || old_build_id.is_none() {
+        return Ok(None);
+    }
+    if let Some(Manifest::V1(_)) = manifest {
+        return Ok(None);
+    }
+    if let Some(Manifest::V1(_)) = old_manifest {
+        return Ok(None);
+    }
+
+    if build_id == &old_build_id {
+        return Ok(None);
+    }
+
+    let build_id = build_id.clone().unwrap();
+
+    let product_id = if let Some(manifest) = manifest {
+        manifest.product_id()
+    } else {
+        return Ok(None);
+    };
+
+    let depots = match get_patch_depots(
+        reqwest_client,
+        &product_id,
+        &old_build_id.unwrap(),
+        &build_id,
+    )
+    .await?
+    {
+        Some(depots) => depots,
+        None => return Ok(None),
+    };
+
+    let wanted_depots =
+        depots.wanted_depots(&product_id, &dlcs, new_language, old_language, bitness);
+
+    let file_patches = get_patch_files(reqwest_client, wanted_depots).await?;
+    Ok(Some(file_patches))
+}
+
+/// Fetches the diffs listed in the patch depots
+async fn get_patch_files(
+    reqwest_client: &Client,
+    wanted_depots: Vec<&ManifestDepot>,
+) -> Result<Vec<FileList>, crate::Error> {
     let mut file_patches: Vec<FileList> = Vec::new();
     for depot in wanted_depots {
         let url = format!(
@@ -156,5 +213,455 @@ pub async fn get_patches(
         file_patches.push(FileList::new(depot.product_id().to_owned(), patches));
     }
 
-    Ok(Some(file_patches))
+    Ok(file_patches)
+}
+
+/// Controls when patches are used instead of downloading updated files
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PatchPolicy {
+    /// Patch only when it's cheaper than downloading the file
+    #[default]
+    Auto,
+    /// Patch every file that has a patch available
+    PatchesOnly,
+    /// Always download updated files
+    NeverPatch,
+}
+
+/// Selects patch depots in [`plan_patches`]
+pub struct PatchFilter<'a> {
+    /// DLCs installed in both builds
+    pub dlcs: &'a [String],
+    pub new_language: &'a String,
+    pub old_language: &'a String,
+    pub bitness: Option<&'a OsBitness>,
+    pub policy: PatchPolicy,
+    /// Password of the private branch, needed to list its intermediate builds
+    pub branch_password: Option<&'a String>,
+}
+
+/// Diffs of a single patch, by lowercase file path
+type HopPatches = HashMap<String, (String, v2::DepotDiff)>;
+
+struct FileInfo {
+    md5: Option<String>,
+    compressed_size: u64,
+}
+
+fn file_infos(lists: &[FileList]) -> HashMap<String, FileInfo> {
+    let mut infos = HashMap::new();
+    for list in lists {
+        for entry in &list.files {
+            let md5 = match entry {
+                DepotEntry::V1(v1::DepotEntry::File(f)) => Some(f.hash().clone()),
+                DepotEntry::V2(v2::DepotEntry::File(f)) => f
+                    .md5()
+                    .clone()
+                    .or_else(|| (f.chunks().len() == 1).then(|| f.chunks()[0].md5().clone())),
+                _ => continue,
+            };
+            infos.insert(
+                entry.path().to_lowercase(),
+                FileInfo {
+                    md5,
+                    compressed_size: entry.compressed_size() as u64,
+                },
+            );
+        }
+    }
+    infos
+}
+
+/// Diffs to apply in order to get from the old file to the new one  
+/// Hops that didn't change the file are skipped, checksums have to match between the steps
+fn chain_for_file<'a>(
+    path: &str,
+    hops: &[&'a HopPatches],
+    old_md5: Option<&String>,
+    new_md5: Option<&String>,
+) -> Option<Vec<&'a (String, v2::DepotDiff)>> {
+    let mut current = old_md5.cloned();
+    let mut chain = Vec::new();
+    for hop in hops {
+        if let Some(entry) = hop.get(path) {
+            if current
+                .as_ref()
+                .is_some_and(|md5| md5 != entry.1.md5_source())
+            {
+                return None;
+            }
+            current = Some(entry.1.md5_target().clone());
+            chain.push(entry);
+        }
+    }
+    if chain.is_empty() {
+        return None;
+    }
+    match (current, new_md5) {
+        (Some(current), Some(new_md5)) if &current != new_md5 => None,
+        _ => Some(chain),
+    }
+}
+
+fn diff_compressed_size(diff: &v2::DepotDiff) -> u64 {
+    diff.chunks()
+        .iter()
+        .fold(0, |acc, ch| acc + *ch.compressed_size() as u64)
+}
+
+/// Returns download cost of the files touched by any of the patches
+/// and paths that can be patched with the given hops
+fn plan_cost(
+    hops: &[&HopPatches],
+    candidates: &HashSet<String>,
+    old: &HashMap<String, FileInfo>,
+    new: &HashMap<String, FileInfo>,
+) -> (u64, Vec<String>) {
+    let mut cost = 0;
+    let mut patched = Vec::new();
+    for path in candidates {
+        let Some(new_file) = new.get(path) else {
+            continue;
+        };
+        let old_md5 = old.get(path).and_then(|f| f.md5.as_ref());
+        if old_md5.is_some() && old_md5 == new_file.md5.as_ref() {
+            continue;
+        }
+        match chain_for_file(path, hops, old_md5, new_file.md5.as_ref()) {
+            Some(chain) if old.contains_key(path) => {
+                cost += chain
+                    .iter()
+                    .fold(0, |acc, (_, diff)| acc + diff_compressed_size(diff));
+                patched.push(path.clone());
+            }
+            _ => cost += new_file.compressed_size,
+        }
+    }
+    (cost, patched)
+}
+
+/// Sequences of build indexes from the installed build (0) to the target build (`last`)  
+/// Direct patch, then chains through consecutive builds entered and left at any point
+fn candidate_paths(last: usize) -> Vec<Vec<usize>> {
+    let mut paths = vec![vec![0, last]];
+    for start in 1..last {
+        for end in start..last {
+            let mut path = vec![0];
+            path.extend(start..=end);
+            path.push(last);
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+async fn get_hop_patches(
+    reqwest_client: &Client,
+    product_id: &String,
+    from_build_id: &str,
+    to_build_id: &str,
+    filter: &PatchFilter<'_>,
+) -> Result<Option<HopPatches>, crate::Error> {
+    let Some(depots) =
+        get_patch_depots(reqwest_client, product_id, from_build_id, to_build_id).await?
+    else {
+        return Ok(None);
+    };
+    let wanted_depots = depots.wanted_depots(
+        product_id,
+        filter.dlcs,
+        filter.new_language,
+        filter.old_language,
+        filter.bitness,
+    );
+    let mut hop = HopPatches::new();
+    for list in get_patch_files(reqwest_client, wanted_depots).await? {
+        for entry in list.files {
+            if let DepotEntry::V2(v2::DepotEntry::Diff(diff)) = entry {
+                let path = DepotEntry::V2(v2::DepotEntry::Diff(diff.clone()))
+                    .path()
+                    .to_lowercase();
+                hop.insert(path, (list.product_id.clone(), diff));
+            }
+        }
+    }
+    Ok(Some(hop))
+}
+
+/// Plans patches between the builds, chaining patches of intermediate builds
+/// when there is no direct patch or the chain is cheaper  
+/// The plan with the fewest bytes to download is picked, including files that can't be patched.
+/// Chained diffs of a file are listed one after another in the order they need to be applied
+pub async fn plan_patches(
+    reqwest_client: &Client,
+    manifest: &Manifest,
+    build_id: &str,
+    old_build_id: &str,
+    filter: &PatchFilter<'_>,
+    new_depots: &[FileList],
+    old_depots: &[FileList],
+) -> Result<Option<Vec<FileList>>, crate::Error> {
+    let Manifest::V2(mv2) = manifest else {
+        return Ok(None);
+    };
+    if build_id == old_build_id {
+        return Ok(None);
+    }
+    let product_id = manifest.product_id();
+
+    // Find builds published between the installed and the target one
+    let mut build_ids = vec![old_build_id.to_string()];
+    let platform: Option<Platform> =
+        serde_json::from_value(serde_json::Value::String(mv2.platform().clone())).ok();
+    // Files are downloaded anyway when patching is disabled, the direct patch only lists them
+    if let Some(platform) = platform.filter(|_| filter.policy != PatchPolicy::NeverPatch) {
+        match super::get_builds(
+            reqwest_client,
+            &product_id,
+            platform,
+            None,
+            filter.branch_password.cloned(),
+        )
+        .await
+        {
+            Ok(builds) => {
+                if let (Some(old), Some(new)) =
+                    (builds.find_by_id(old_build_id), builds.find_by_id(build_id))
+                {
+                    let mut intermediate: Vec<_> = builds
+                        .items()
+                        .iter()
+                        .filter(|b| {
+                            *b.generation() == 2
+                                && b.branch() == new.branch()
+                                && b.date_published() > old.date_published()
+                                && b.date_published() < new.date_published()
+                        })
+                        .collect();
+                    intermediate.sort_by_key(|b| *b.date_published());
+                    let skip = intermediate.len().saturating_sub(MAX_INTERMEDIATE_BUILDS);
+                    build_ids.extend(intermediate[skip..].iter().map(|b| b.build_id().clone()));
+                }
+            }
+            Err(err) => log::warn!("Failed to get builds for patch planning {}", err),
+        }
+    }
+    build_ids.push(build_id.to_string());
+    let last = build_ids.len() - 1;
+
+    let fetch_hop = |(from, to): (usize, usize)| {
+        let build_ids = &build_ids;
+        let product_id = &product_id;
+        async move {
+            log::debug!("Getting patch {} -> {}", build_ids[from], build_ids[to]);
+            let hop = get_hop_patches(
+                reqwest_client,
+                product_id,
+                &build_ids[from],
+                &build_ids[to],
+                filter,
+            )
+            .await;
+            ((from, to), hop)
+        }
+    };
+
+    // The direct patch is the most likely plan, intermediate hops are fetched together after it
+    let paths = candidate_paths(last);
+    let (direct, hop) = fetch_hop((0, last)).await;
+    let mut hops: HashMap<(usize, usize), Option<HopPatches>> = HashMap::from([(direct, hop?)]);
+    let edges: HashSet<(usize, usize)> = paths
+        .iter()
+        .flat_map(|path| path.windows(2).map(|edge| (edge[0], edge[1])))
+        .filter(|edge| !hops.contains_key(edge))
+        .collect();
+    let fetched: Vec<_> = futures::stream::iter(edges.into_iter().map(fetch_hop))
+        .buffer_unordered(HOP_FETCH_CONCURRENCY)
+        .collect()
+        .await;
+    for ((from, to), hop) in fetched {
+        // Chains are only an optimization, a missing hop rules out the paths through it
+        let hop = hop.unwrap_or_else(|err| {
+            log::warn!(
+                "Failed to get patch {} -> {} {}",
+                build_ids[from],
+                build_ids[to],
+                err
+            );
+            None
+        });
+        hops.insert((from, to), hop);
+    }
+
+    let old = file_infos(old_depots);
+    let new = file_infos(new_depots);
+    Ok(select_plan(&hops, &paths, filter.policy, &old, &new))
+}
+
+/// Picks the cheapest of the candidate paths and lists its diffs  
+/// Under [`PatchPolicy::NeverPatch`] the available patches are still listed,
+/// so that the decision to download the files is recorded
+fn select_plan(
+    hops: &HashMap<(usize, usize), Option<HopPatches>>,
+    paths: &[Vec<usize>],
+    policy: PatchPolicy,
+    old: &HashMap<String, FileInfo>,
+    new: &HashMap<String, FileInfo>,
+) -> Option<Vec<FileList>> {
+    let candidates: HashSet<String> = hops
+        .values()
+        .flatten()
+        .flat_map(|hop| hop.keys().cloned())
+        .collect();
+
+    // Downloading every file is the baseline, unless the costs don't matter
+    let (mut best_cost, _) = match policy {
+        PatchPolicy::Auto => plan_cost(&[], &candidates, old, new),
+        PatchPolicy::PatchesOnly | PatchPolicy::NeverPatch => (u64::MAX, Vec::new()),
+    };
+    let mut best: Option<(Vec<&HopPatches>, Vec<String>)> = None;
+    for path in paths {
+        let path_hops: Option<Vec<&HopPatches>> = path
+            .windows(2)
+            .map(|edge| hops.get(&(edge[0], edge[1])).and_then(|h| h.as_ref()))
+            .collect();
+        let Some(path_hops) = path_hops else {
+            continue;
+        };
+        let (cost, patched) = plan_cost(&path_hops, &candidates, old, new);
+        if cost < best_cost && !patched.is_empty() {
+            log::debug!("Patch plan {:?} costs {} bytes", path, cost);
+            best_cost = cost;
+            best = Some((path_hops, patched));
+        }
+    }
+
+    let (path_hops, mut patched) = best?;
+    patched.sort();
+
+    let mut file_patches: Vec<FileList> = Vec::new();
+    for path in patched {
+        let new_md5 = new.get(&path).and_then(|f| f.md5.as_ref());
+        let old_md5 = old.get(&path).and_then(|f| f.md5.as_ref());
+        let chain = chain_for_file(&path, &path_hops, old_md5, new_md5).unwrap();
+        let product_id = &chain[0].0;
+        let entries = chain
+            .iter()
+            .map(|(_, diff)| DepotEntry::V2(v2::DepotEntry::Diff(diff.clone())));
+        match file_patches
+            .iter_mut()
+            .find(|l| &l.product_id == product_id)
+        {
+            Some(list) => list.files.extend(entries),
+            None => file_patches.push(FileList::new(product_id.clone(), entries.collect())),
+        }
+    }
+
+    Some(file_patches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(path: &str, source: &str, target: &str, size: i64) -> v2::DepotDiff {
+        serde_json::from_value(serde_json::json!({
+            "md5_source": source,
+            "md5_target": target,
+            "path_source": path,
+            "path_target": path,
+            "md5": "d",
+            "chunks": [{"compressedMd5": "c", "md5": "m", "size": size, "compressedSize": size}]
+        }))
+        .unwrap()
+    }
+
+    fn info(md5: &str, compressed_size: u64) -> FileInfo {
+        FileInfo {
+            md5: Some(md5.to_string()),
+            compressed_size,
+        }
+    }
+
+    #[test]
+    fn chained_patches() {
+        let mut first = HopPatches::new();
+        first.insert("a.bin".into(), ("1".into(), diff("a.bin", "a1", "a2", 10)));
+        first.insert("b.bin".into(), ("1".into(), diff("b.bin", "b1", "b2", 10)));
+        let mut second = HopPatches::new();
+        second.insert("a.bin".into(), ("1".into(), diff("a.bin", "a2", "a3", 10)));
+        // Doesn't start from the installed version of c.bin
+        second.insert("c.bin".into(), ("1".into(), diff("c.bin", "cX", "c2", 10)));
+
+        let old = HashMap::from([
+            ("a.bin".to_string(), info("a1", 100)),
+            ("b.bin".to_string(), info("b1", 100)),
+            ("c.bin".to_string(), info("c1", 100)),
+        ]);
+        let new = HashMap::from([
+            ("a.bin".to_string(), info("a3", 100)),
+            ("b.bin".to_string(), info("b2", 100)),
+            ("c.bin".to_string(), info("c2", 100)),
+        ]);
+        let candidates: HashSet<String> = ["a.bin", "b.bin", "c.bin"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let chain = chain_for_file("a.bin", &[&first, &second], Some(&"a1".into()), None).unwrap();
+        assert_eq!(chain.len(), 2);
+        // Second hop is missing
+        assert!(
+            chain_for_file("a.bin", &[&first], Some(&"a1".into()), Some(&"a3".into())).is_none()
+        );
+
+        let (cost, mut patched) = plan_cost(&[&first, &second], &candidates, &old, &new);
+        patched.sort();
+        assert_eq!(patched, vec!["a.bin", "b.bin"]);
+        assert_eq!(cost, 10 + 10 + 10 + 100);
+
+        let (cost, patched) = plan_cost(&[], &candidates, &old, &new);
+        assert!(patched.is_empty());
+        assert_eq!(cost, 300);
+    }
+
+    #[test]
+    fn candidate_build_paths() {
+        assert_eq!(candidate_paths(1), vec![vec![0, 1]]);
+        assert_eq!(
+            candidate_paths(3),
+            vec![vec![0, 3], vec![0, 1, 3], vec![0, 1, 2, 3], vec![0, 2, 3]]
+        );
+    }
+
+    #[test]
+    fn never_patch_lists_patches() {
+        let mut direct = HopPatches::new();
+        direct.insert("a.bin".into(), ("1".into(), diff("a.bin", "a1", "a2", 10)));
+        direct.insert("b.bin".into(), ("1".into(), diff("b.bin", "b1", "b2", 200)));
+        let hops = HashMap::from([((0, 1), Some(direct))]);
+        let old = HashMap::from([
+            ("a.bin".to_string(), info("a1", 100)),
+            ("b.bin".to_string(), info("b1", 100)),
+        ]);
+        let new = HashMap::from([
+            ("a.bin".to_string(), info("a2", 100)),
+            ("b.bin".to_string(), info("b2", 100)),
+        ]);
+        let paths = candidate_paths(1);
+        let paths_of = |plan: Option<Vec<FileList>>| -> Vec<String> {
+            plan.unwrap()
+                .iter()
+                .flat_map(|l| l.files.iter().map(|f| f.path()))
+                .collect()
+        };
+
+        // Downloading both files is cheaper
+        assert!(select_plan(&hops, &paths, PatchPolicy::Auto, &old, &new).is_none());
+        let plan = select_plan(&hops, &paths, PatchPolicy::NeverPatch, &old, &new);
+        assert_eq!(paths_of(plan), vec!["a.bin", "b.bin"]);
+
+        let empty = HashMap::from([((0, 1), Some(HopPatches::new()))]);
+        assert!(select_plan(&empty, &paths, PatchPolicy::NeverPatch, &old, &new).is_none());
+    }
 }
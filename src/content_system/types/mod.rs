@@ -91,11 +91,21 @@ impl traits::EntryUtils for DepotEntry {
     }
 }
 
+#[cfg(feature = "downloader")]
+impl DepotEntry {
+    pub fn is_executable(&self) -> bool {
+        match self {
+            Self::V1(v1) => v1.is_executable(),
+            Self::V2(v2) => v2.is_executable(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum Manifest {
-    V1(v1::Manifest),
-    V2(v2::Manifest),
+    V1(Box<v1::Manifest>),
+    V2(Box<v2::Manifest>),
 }
 
 impl Manifest {
@@ -204,13 +214,14 @@ impl Manifest {
     }
 
     /// Returns a tuple of (compressed_size, decompressed_size)
-    /// based on wanted language and dlcs
+    /// based on wanted language, dlcs and bitness (v2 only)
     /// The actual download size may slightly differ depending on the implementation
     /// Includes dependencies sizes if dependencies_manifest is provided
     pub fn install_size<I, V>(
         &self,
         language: &String,
         dlcs: I,
+        bitness: Option<&OsBitness>,
         dependenies_manifest: Option<&DependenciesManifest>,
     ) -> (u64, u64)
     where
@@ -258,6 +269,10 @@ impl Manifest {
                         continue;
                     }
 
+                    if !depot.matches_bitness(bitness) {
+                        continue;
+                    }
+
                     if depot.languages().contains(&"*".to_string())
                         || depot.languages().contains(language)
                     {
@@ -284,11 +299,18 @@ impl Manifest {
         (download_size, install_size)
     }
 
+    /// Fetches file lists of depots matching the language and dlcs
+    ///
+    /// * `bitness` - architecture of v2 depots to fetch, None fetches all of them
+    /// * `include_offline_depot` - whether to fetch v2 depot containing files
+    ///   required to run the game without Galaxy
     pub async fn get_depots<I, V>(
         &self,
         reqwest_client: &Client,
         language: &String,
         dlcs: I,
+        bitness: Option<&OsBitness>,
+        include_offline_depot: bool,
     ) -> Result<Vec<FileList>, crate::Error>
     where
         I: IntoIterator<Item = V> + Copy,
@@ -362,25 +384,18 @@ impl Manifest {
                         continue;
                     }
 
-                    let galaxy_path = crate::utils::hash_to_galaxy_path(depot.manifest());
-                    let url = format!("{}/content-system/v2/meta/{}", GOG_CDN, galaxy_path);
-                    let response = reqwest_exponential_backoff(reqwest_client.get(url))
-                        .await
-                        .map_err(request_error)?;
-                    let compressed_manifest = response.bytes().await.map_err(request_error)?;
-
-                    let mut zlib = ZlibDecoder::new(&compressed_manifest[..]);
-                    let mut buffer = Vec::new();
-
-                    zlib.read_to_end(&mut buffer).await.map_err(zlib_error)?;
-
-                    let json_data: v2::DepotDetails =
-                        serde_json::from_slice(&buffer).map_err(serde_error)?;
-                    let (entries, sfc) = json_data.depot.dissolve();
-                    let entries = entries.into_iter().map(DepotEntry::V2).collect();
-                    let mut f_list = FileList::new(depot.product_id().to_owned(), entries);
-                    f_list.sfc = sfc;
-                    depots.push(f_list);
+                    if !depot.matches_bitness(bitness) {
+                        continue;
+                    }
+
+                    depots.push(get_v2_depot(reqwest_client, depot).await?);
+                }
+
+                if include_offline_depot {
+                    if let Some(depot) = mv2.offline_depot() {
+                        log::debug!("Getting offline depot {}", depot.manifest());
+                        depots.push(get_v2_depot(reqwest_client, depot).await?);
+                    }
                 }
             }
         }
@@ -388,12 +403,60 @@ impl Manifest {
     }
 }
 
+async fn get_v2_depot(
+    reqwest_client: &Client,
+    depot: &v2::ManifestDepot,
+) -> Result<FileList, crate::Error> {
+    let galaxy_path = crate::utils::hash_to_galaxy_path(depot.manifest());
+    let url = format!("{}/content-system/v2/meta/{}", GOG_CDN, galaxy_path);
+    let response = reqwest_exponential_backoff(reqwest_client.get(url))
+        .await
+        .map_err(request_error)?;
+    let compressed_manifest = response.bytes().await.map_err(request_error)?;
+
+    let mut zlib = ZlibDecoder::new(&compressed_manifest[..]);
+    let mut buffer = Vec::new();
+
+    zlib.read_to_end(&mut buffer).await.map_err(zlib_error)?;
+
+    let json_data: v2::DepotDetails = serde_json::from_slice(&buffer).map_err(serde_error)?;
+    let (entries, sfc) = json_data.depot.dissolve();
+    let entries = entries.into_iter().map(DepotEntry::V2).collect();
+    let mut f_list = FileList::new(depot.product_id().to_owned(), entries);
+    f_list.sfc = sfc;
+    Ok(f_list)
+}
+
+/// Architecture of the v2 depot
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum OsBitness {
+    #[serde(rename = "32")]
+    Bit32,
+    #[serde(rename = "64")]
+    Bit64,
+    /// Architecture this version doesn't know about, such depots match only when no bitness is selected
+    #[serde(other)]
+    Unknown,
+}
+
+impl Display for OsBitness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::Bit32 => f.write_str("32"),
+            Self::Bit64 => f.write_str("64"),
+            Self::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum Platform {
     Windows,
     OsX,
-    //Linux
+    /// Galaxy builds don't exist for Linux,
+    /// native games are installed from offline installers instead
+    Linux,
 }
 
 impl Display for Platform {
@@ -401,7 +464,7 @@ impl Display for Platform {
         match *self {
             Self::Windows => f.write_str("windows"),
             Self::OsX => f.write_str("osx"),
-            //Self::Linux => f.write_str("linux"),
+            Self::Linux => f.write_str("linux"),
         }
     }
 }
@@ -413,6 +476,99 @@ pub struct BuildResponse {
     items: Vec<Build>,
 }
 
+impl BuildResponse {
+    /// Groups builds by branch, None being the default branch  
+    /// Builds in each group keep the order of the response (newest first)
+    pub fn by_branch(&self) -> HashMap<Option<String>, Vec<&Build>> {
+        let mut branches: HashMap<Option<String>, Vec<&Build>> = HashMap::new();
+        for build in &self.items {
+            branches
+                .entry(build.branch.clone())
+                .or_default()
+                .push(build);
+        }
+        branches
+    }
+
+    /// Returns names of available branches, None being the default branch
+    pub fn branches(&self) -> Vec<Option<String>> {
+        let mut branches: Vec<Option<String>> = Vec::new();
+        for build in &self.items {
+            if !branches.contains(&build.branch) {
+                branches.push(build.branch.clone());
+            }
+        }
+        branches
+    }
+
+    /// Returns generations used by the builds
+    pub fn generations(&self) -> Vec<u32> {
+        let mut generations: Vec<u32> = self.items.iter().map(|b| b.generation).collect();
+        generations.sort_unstable();
+        generations.dedup();
+        generations
+    }
+
+    /// Returns the most recently published build of the branch  
+    /// * `branch` - None for the default branch
+    pub fn latest(&self, branch: Option<&str>) -> Option<&Build> {
+        self.items
+            .iter()
+            .filter(|b| b.branch.as_deref() == branch)
+            .max_by_key(|b| b.date_published)
+    }
+
+    /// Returns the most recently published public build of the branch  
+    /// Builds of private branches aren't public, see [`Self::latest`] for them
+    /// * `branch` - None for the default branch
+    pub fn latest_public(&self, branch: Option<&str>) -> Option<&Build> {
+        self.items
+            .iter()
+            .filter(|b| b.public && b.branch.as_deref() == branch)
+            .max_by_key(|b| b.date_published)
+    }
+
+    /// Returns the most recently published build of every branch
+    pub fn latest_per_branch(&self) -> HashMap<Option<String>, &Build> {
+        self.by_branch()
+            .into_iter()
+            .filter_map(|(branch, builds)| {
+                let latest = builds.into_iter().max_by_key(|b| b.date_published)?;
+                Some((branch, latest))
+            })
+            .collect()
+    }
+
+    /// Finds the build by its version name  
+    /// If the version was published multiple times, the newest build is returned
+    pub fn find_by_version(&self, version_name: &str, branch: Option<&str>) -> Option<&Build> {
+        self.items
+            .iter()
+            .filter(|b| b.branch.as_deref() == branch && b.version_name == version_name)
+            .max_by_key(|b| b.date_published)
+    }
+
+    /// Finds the build by its id
+    pub fn find_by_id(&self, build_id: &str) -> Option<&Build> {
+        self.items.iter().find(|b| b.build_id == build_id)
+    }
+
+    /// Checks if there is a newer build on the branch than the installed one
+    pub fn is_outdated(&self, build_id: &str, branch: Option<&str>) -> bool {
+        let Some(latest) = self.latest(branch) else {
+            return false;
+        };
+        if latest.build_id == build_id {
+            return false;
+        }
+        match self.find_by_id(build_id) {
+            Some(installed) => installed.date_published < latest.date_published,
+            // Installed build is no longer listed
+            None => true,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Getters, Clone, Debug)]
 pub struct Build {
     build_id: String,
@@ -440,6 +596,49 @@ pub struct Endpoint {
     pub(crate) fallback_only: bool,
 }
 
+impl Endpoint {
+    /// Endpoint pointing at a local mirror of the chunk store, see `content_system::mirror`  
+    /// `store_path` is the `path` parameter of the CDN endpoint the mirror replaces
+    /// e.g. `/content-system/v2/store/1207658924`.  
+    /// The endpoint has the highest priority so that it's preferred over the CDN
+    pub fn mirror(base_url: &str, store_path: &str) -> Self {
+        Self {
+            endpoint_name: "mirror".to_string(),
+            url: String::new(),
+            url_format: "{base_url}{path}".to_string(),
+            parameters: HashMap::from_iter([
+                (
+                    "base_url".to_string(),
+                    serde_json::Value::String(base_url.trim_end_matches('/').to_string()),
+                ),
+                (
+                    "path".to_string(),
+                    serde_json::Value::String(store_path.to_string()),
+                ),
+            ]),
+            priority: u32::MAX,
+            max_fails: 3,
+            supports_generation: vec![2],
+            fallback_only: false,
+        }
+    }
+
+    /// Whether the endpoint was created with [`Self::mirror`]
+    pub fn is_mirror(&self) -> bool {
+        self.endpoint_name == "mirror"
+    }
+
+    /// Time after which the secure link stops working, parsed from the `expires_at` parameter
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        let timestamp = match self.parameters.get("expires_at")? {
+            serde_json::Value::String(v) => v.parse::<i64>().ok()?,
+            serde_json::Value::Number(n) => n.as_i64()?,
+            _ => return None,
+        };
+        DateTime::from_timestamp(timestamp, 0)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SizeInfo {
     pub disk_size: u64,
@@ -113,6 +113,16 @@ impl super::traits::EntryUtils for DepotEntry {
     }
 }
 
+#[cfg(feature = "downloader")]
+impl DepotEntry {
+    pub fn is_executable(&self) -> bool {
+        match self {
+            Self::File(f) => *f.executable(),
+            _ => false,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Getters, Clone, Debug)]
 pub struct DepotFile {
     path: String,
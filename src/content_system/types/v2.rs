@@ -1,4 +1,5 @@
 use crate::content_system::languages;
+use crate::content_system::types::OsBitness;
 use derive_getters::{Dissolve, Getters};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -13,6 +14,8 @@ pub struct Manifest {
     dependencies: Vec<String>,
     depots: Vec<ManifestDepot>,
     install_directory: String,
+    /// Depot with goggame files required to run the game without Galaxy
+    offline_depot: Option<ManifestDepot>,
     platform: String,
     products: Vec<ManifestProduct>,
     #[serde(default)]
@@ -34,10 +37,24 @@ pub struct ManifestDepot {
     languages: Vec<String>,
     manifest: String,
     product_id: String,
+    /// Architectures the depot is meant for, empty if it's not architecture specific
+    #[serde(default)]
+    os_bitness: Vec<OsBitness>,
     #[serde(flatten)]
     unknown_fields: HashMap<String, serde_json::Value>,
 }
 
+impl ManifestDepot {
+    /// Whether depot should be installed on the given architecture  
+    /// None matches every depot
+    pub fn matches_bitness(&self, bitness: Option<&OsBitness>) -> bool {
+        match bitness {
+            Some(bitness) => self.os_bitness.is_empty() || self.os_bitness.contains(bitness),
+            None => true,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Getters, Debug, Clone)]
 pub struct ManifestProduct {
     name: String,
@@ -120,6 +137,16 @@ impl super::traits::EntryUtils for DepotEntry {
     }
 }
 
+#[cfg(feature = "downloader")]
+impl DepotEntry {
+    pub fn is_executable(&self) -> bool {
+        match self {
+            Self::File(f) => f.flags().iter().any(|f| f == "executable"),
+            _ => false,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Getters, Clone, Debug)]
 pub struct DepotDirectory {
     path: String,
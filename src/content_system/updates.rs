@@ -0,0 +1,399 @@
+use futures::StreamExt;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use super::patches;
+use super::types::{v1, Build, Manifest, OsBitness, Platform};
+use crate::{Core, Error};
+
+/// Maximum number of games checked at once
+const UPDATE_CHECK_CONCURRENCY: usize = 5;
+
+/// Installed game state used for checking for updates
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InstalledGame {
+    pub product_id: String,
+    pub platform: Platform,
+    pub build_id: String,
+    /// None for the default branch
+    pub branch: Option<String>,
+    /// Password of the private branch
+    pub branch_password: Option<String>,
+    /// Installed language, used for download size estimation
+    pub language: String,
+    /// Installed DLCs, used for download size estimation
+    pub dlcs: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum UpdateStatus {
+    UpToDate,
+    UpdateAvailable {
+        /// Build to update to
+        build: Box<Build>,
+        /// Estimated size of the update, None if it couldn't be determined
+        estimate: Option<UpdateEstimate>,
+    },
+    /// Installed build is no longer listed on the branch
+    BuildRemoved,
+}
+
+impl UpdateStatus {
+    /// Version name of the build to update to
+    pub fn version_name(&self) -> Option<&String> {
+        match self {
+            Self::UpdateAvailable { build, .. } => Some(build.version_name()),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) async fn update_check(
+    core: &Core,
+    installed: &[InstalledGame],
+) -> Vec<Result<UpdateStatus, Error>> {
+    futures::stream::iter(installed)
+        .map(|game| check_game(core, game))
+        .buffered(UPDATE_CHECK_CONCURRENCY)
+        .collect()
+        .await
+}
+
+async fn check_game(core: &Core, game: &InstalledGame) -> Result<UpdateStatus, Error> {
+    let builds = core
+        .get_builds(
+            &game.product_id,
+            game.platform.clone(),
+            game.branch_password.clone(),
+        )
+        .await?;
+
+    let branch = game.branch.as_deref();
+    let Some(installed) = builds
+        .find_by_id(&game.build_id)
+        .filter(|b| b.branch().as_deref() == branch)
+    else {
+        return Ok(UpdateStatus::BuildRemoved);
+    };
+    if !builds.is_outdated(installed.build_id(), branch) {
+        return Ok(UpdateStatus::UpToDate);
+    }
+    let target = builds.latest(branch).unwrap().clone();
+
+    let estimate = match estimate_download(core, game, installed, &target).await {
+        Ok(estimate) => Some(estimate),
+        Err(err) => {
+            log::warn!(
+                "Failed to estimate update size for {}: {}",
+                game.product_id,
+                err
+            );
+            None
+        }
+    };
+
+    Ok(UpdateStatus::UpdateAvailable {
+        build: Box::new(target),
+        estimate,
+    })
+}
+
+async fn estimate_download(
+    core: &Core,
+    game: &InstalledGame,
+    installed: &Build,
+    target: &Build,
+) -> Result<UpdateEstimate, Error> {
+    let old_manifest = core.get_manifest(installed).await?;
+    let new_manifest = core.get_manifest(target).await?;
+    let mut options = EstimateOptions::new(&game.language);
+    options.dlcs.clone_from(&game.dlcs);
+    options.branch_password.clone_from(&game.branch_password);
+    estimate_update(
+        core.reqwest_client(),
+        &old_manifest,
+        installed.build_id(),
+        &new_manifest,
+        target.build_id(),
+        &options,
+    )
+    .await
+}
+
+/// Parameters of [`estimate_update`]
+#[derive(Clone, Debug)]
+pub struct EstimateOptions {
+    /// Language to update to
+    pub language: String,
+    /// Installed language, defaults to `language`
+    pub old_language: Option<String>,
+    /// DLCs to update to
+    pub dlcs: Vec<String>,
+    /// Installed DLCs, defaults to `dlcs`
+    pub old_dlcs: Option<Vec<String>>,
+    pub bitness: Option<OsBitness>,
+    /// Password of the private branch, used to look for patches through intermediate builds
+    pub branch_password: Option<String>,
+    /// Compare the file lists of both builds instead of depot metadata  
+    /// Gives exact numbers at the cost of fetching every depot manifest,
+    /// requires `downloader` feature and is ignored otherwise
+    pub full_diff: bool,
+}
+
+impl EstimateOptions {
+    pub fn new(language: &str) -> Self {
+        Self {
+            language: language.to_string(),
+            old_language: None,
+            dlcs: Vec::new(),
+            old_dlcs: None,
+            bitness: None,
+            branch_password: None,
+            full_diff: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct UpdateEstimate {
+    /// Compressed size of data to download
+    pub download_size: u64,
+    /// Change of the installation size
+    pub disk_delta: i64,
+    /// Number of items updated with patches
+    pub patched: usize,
+    /// Number of items downloaded in full
+    pub redownloaded: usize,
+    /// Whether the numbers come from the file lists  
+    /// If false, `patched` and `redownloaded` are depot counts and `download_size`
+    /// doesn't include new files of the patched depots
+    pub full_diff: bool,
+}
+
+/// Depot metadata common for v1 and v2 manifests
+#[derive(Debug, PartialEq)]
+struct DepotInfo<'a> {
+    product_id: &'a str,
+    languages: &'a [String],
+    manifest: &'a str,
+    compressed_size: u64,
+    size: u64,
+}
+
+fn wanted_depots<'a>(
+    manifest: &'a Manifest,
+    language: &String,
+    dlcs: &[String],
+    bitness: Option<&OsBitness>,
+) -> Vec<DepotInfo<'a>> {
+    let mut depots = Vec::new();
+    let language_matches =
+        |languages: &[String]| languages.iter().any(|l| l == "*" || l == language);
+    match manifest {
+        Manifest::V1(mv1) => {
+            let root_game_id = mv1.product().root_game_id();
+            for depot in mv1.product().depots() {
+                if let v1::ManifestDepot::Files {
+                    languages,
+                    size,
+                    game_ids,
+                    manifest,
+                    ..
+                } = depot
+                {
+                    if !game_ids.contains(root_game_id)
+                        && !game_ids.iter().any(|id| dlcs.contains(id))
+                    {
+                        continue;
+                    }
+                    if !language_matches(languages) {
+                        continue;
+                    }
+                    let size = size.parse::<u64>().unwrap_or_default();
+                    depots.push(DepotInfo {
+                        product_id: game_ids.first().map(|s| s.as_str()).unwrap_or_default(),
+                        languages,
+                        manifest,
+                        compressed_size: size,
+                        size,
+                    });
+                }
+            }
+        }
+        Manifest::V2(mv2) => {
+            let root_game_id = mv2.base_product_id();
+            for depot in mv2.depots() {
+                if depot.product_id() != root_game_id && !dlcs.contains(depot.product_id()) {
+                    continue;
+                }
+                if !language_matches(depot.languages()) || !depot.matches_bitness(bitness) {
+                    continue;
+                }
+                depots.push(DepotInfo {
+                    product_id: depot.product_id(),
+                    languages: depot.languages(),
+                    manifest: depot.manifest(),
+                    compressed_size: *depot.compressed_size() as u64,
+                    size: *depot.size() as u64,
+                });
+            }
+        }
+    }
+    depots
+}
+
+/// Estimates the cost of updating between two builds without touching the installation  
+/// By default only the manifests and the patch index are used,
+/// see [`EstimateOptions::full_diff`] for exact numbers
+pub async fn estimate_update(
+    reqwest_client: &Client,
+    old_manifest: &Manifest,
+    old_build_id: &str,
+    new_manifest: &Manifest,
+    new_build_id: &str,
+    options: &EstimateOptions,
+) -> Result<UpdateEstimate, Error> {
+    let old_language = options.old_language.as_ref().unwrap_or(&options.language);
+    let old_dlcs = options.old_dlcs.as_ref().unwrap_or(&options.dlcs);
+
+    #[cfg(feature = "downloader")]
+    if options.full_diff {
+        return super::downloader::estimate_with_diff(
+            reqwest_client,
+            old_manifest,
+            old_build_id,
+            new_manifest,
+            new_build_id,
+            options,
+        )
+        .await;
+    }
+
+    let new_depots = wanted_depots(
+        new_manifest,
+        &options.language,
+        &options.dlcs,
+        options.bitness.as_ref(),
+    );
+    let old_depots = wanted_depots(
+        old_manifest,
+        old_language,
+        old_dlcs,
+        options.bitness.as_ref(),
+    );
+
+    let new_size = new_depots.iter().fold(0, |acc, d| acc + d.size);
+    let old_size = old_depots.iter().fold(0, |acc, d| acc + d.size);
+    let mut estimate = UpdateEstimate {
+        disk_delta: new_size as i64 - old_size as i64,
+        ..Default::default()
+    };
+
+    let patch = match (old_manifest, new_manifest) {
+        (Manifest::V2(_), Manifest::V2(_)) if old_build_id != new_build_id => {
+            patches::get_patch_depots(
+                reqwest_client,
+                &new_manifest.product_id(),
+                old_build_id,
+                new_build_id,
+            )
+            .await?
+        }
+        _ => None,
+    };
+    let re_used_dlcs: Vec<String> = options
+        .dlcs
+        .iter()
+        .filter(|d| old_dlcs.contains(d))
+        .cloned()
+        .collect();
+    let patch_depots = match &patch {
+        Some(patch) => patch.wanted_depots(
+            &new_manifest.product_id(),
+            &re_used_dlcs,
+            &options.language,
+            old_language,
+            options.bitness.as_ref(),
+        ),
+        None => Vec::new(),
+    };
+    let mut used_patches = vec![false; patch_depots.len()];
+
+    for depot in &new_depots {
+        if old_depots.iter().any(|d| d.manifest == depot.manifest) {
+            continue;
+        }
+        // Depot existed in the old build, look for patches that apply to it
+        let was_installed = old_depots
+            .iter()
+            .any(|d| d.product_id == depot.product_id && d.languages == depot.languages);
+        let mut patched = false;
+        if was_installed {
+            for (index, patch_depot) in patch_depots.iter().enumerate() {
+                if patch_depot.product_id() == depot.product_id
+                    && patch_depot
+                        .languages()
+                        .iter()
+                        .any(|l| depot.languages.contains(l))
+                {
+                    patched = true;
+                    if !used_patches[index] {
+                        used_patches[index] = true;
+                        estimate.download_size += *patch_depot.compressed_size() as u64;
+                    }
+                }
+            }
+        }
+        if patched {
+            estimate.patched += 1;
+        } else {
+            estimate.redownloaded += 1;
+            estimate.download_size += depot.compressed_size;
+        }
+    }
+
+    Ok(estimate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(english_manifest: &str, english_size: u64) -> Manifest {
+        let data = format!(
+            r#"{{"product":{{"timestamp":1,"depots":[
+                {{"languages":["Neutral"],"size":"100","gameIDs":["1"],"systems":["Windows"],"manifest":"common.json"}},
+                {{"languages":["English"],"size":"{english_size}","gameIDs":["1"],"systems":["Windows"],"manifest":"{english_manifest}"}},
+                {{"languages":["German"],"size":"30","gameIDs":["1"],"systems":["Windows"],"manifest":"german.json"}}
+            ],"support_commands":[],"installDirectory":"Test","rootGameID":"1","gameIDs":[],"projectName":"Test"}},"version":1}}"#
+        );
+        serde_json::from_str(&data).unwrap()
+    }
+
+    #[tokio::test]
+    async fn depot_level_estimate() {
+        let old = manifest("english-1.json", 50);
+        let new = manifest("english-2.json", 80);
+        let client = Client::new();
+        let options = EstimateOptions::new("en-US");
+
+        let estimate = estimate_update(&client, &old, "1", &new, "2", &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            estimate,
+            UpdateEstimate {
+                download_size: 80,
+                disk_delta: 30,
+                patched: 0,
+                redownloaded: 1,
+                full_diff: false,
+            }
+        );
+
+        let estimate = estimate_update(&client, &new, "2", &new, "2", &options)
+            .await
+            .unwrap();
+        assert_eq!(estimate, UpdateEstimate::default());
+    }
+}
@@ -3,11 +3,15 @@ use crate::auth::types::Token;
 use crate::constants::{GALAXY_CLIENT_ID, GALAXY_CLIENT_SECRET};
 use crate::content_system::dependencies::{self, DependenciesManifest};
 use crate::content_system::types::{Build, BuildResponse, Manifest, Platform};
+use crate::content_system::updates::{
+    self, EstimateOptions, InstalledGame, UpdateEstimate, UpdateStatus,
+};
 use crate::errors::{maximum_retries_error, serde_error, zlib_error};
 use crate::library::types::GalaxyLibraryItem;
+use crate::products::types::{BonusContent, Downlink, DownloadFile, Installer, ProductDetails};
 use crate::user::types::UserData;
 use crate::utils::reqwest_exponential_backoff;
-use crate::{auth, content_system, errors, user};
+use crate::{auth, content_system, errors, products, user};
 use chrono::Utc;
 use parking_lot::Mutex;
 use std::collections::HashMap;
@@ -259,6 +263,70 @@ impl Core {
         user::get_user_data(&self.reqwest_client, token).await
     }
 
+    /// Get product details along with its downloadable files  
+    /// Requires authentication
+    pub async fn get_product_details(
+        &self,
+        product_id: &str,
+    ) -> Result<ProductDetails, errors::Error> {
+        self.ensure_auth()?;
+        let token = self.obtain_galaxy_token().await?;
+        log::debug!("Getting product details for {}", product_id);
+        products::get_product_details(&self.reqwest_client, token, product_id).await
+    }
+
+    /// List goodies (manuals, soundtracks, wallpapers etc.) available for the product  
+    /// Requires authentication
+    pub async fn get_extras(&self, product_id: &str) -> Result<Vec<BonusContent>, errors::Error> {
+        let details = self.get_product_details(product_id).await?;
+        Ok(details.downloads().bonus_content().clone())
+    }
+
+    /// List offline installers available for the product  
+    /// Requires authentication
+    ///
+    /// * `platform` - only return installers for this platform
+    /// * `language` - only return installers in this language, a code or name e.g `en-US` or `English`
+    pub async fn get_installers(
+        &self,
+        product_id: &str,
+        platform: Option<Platform>,
+        language: Option<&str>,
+    ) -> Result<Vec<Installer>, errors::Error> {
+        let details = self.get_product_details(product_id).await?;
+        // Installer languages are normalized to codes when parsed
+        let language = language
+            .map(|l| content_system::languages::get_language(l).map_or(l, |lang| lang.code));
+        Ok(details
+            .downloads()
+            .installers()
+            .iter()
+            .filter(|i| {
+                platform
+                    .as_ref()
+                    .is_none_or(|p| i.platform().as_ref() == Some(p))
+            })
+            .filter(|i| language.is_none_or(|l| i.language() == l))
+            .cloned()
+            .collect())
+    }
+
+    /// Resolve the downlink of a file obtained with [`Core::get_product_details`]  
+    /// Requires authentication
+    pub async fn get_downlink(&self, file: &DownloadFile) -> Result<Downlink, errors::Error> {
+        self.ensure_auth()?;
+        let token = self.obtain_galaxy_token().await?;
+        products::get_downlink(&self.reqwest_client, token, file.downlink()).await
+    }
+
+    /// Resolve the downlink of a file to the actual CDN url  
+    /// Requires authentication
+    pub async fn get_download_url(&self, file: &DownloadFile) -> Result<String, errors::Error> {
+        let downlink = self.get_downlink(file).await?;
+        let url = products::resolve_url(&self.reqwest_client, downlink.downlink()).await?;
+        Ok(url.to_string())
+    }
+
     /// Get available builds from content-system  
     /// Authorization for this call is optional  
     ///
@@ -283,6 +351,59 @@ impl Core {
             .await
     }
 
+    /// Get the latest public build of the branch  
+    /// Useful for following private branches, since their builds are listed
+    /// only when the branch password is provided. Their builds aren't public,
+    /// so with the password the latest build is returned regardless
+    ///
+    /// * `branch` - None for the default branch
+    /// * `password` - password of the private branch
+    pub async fn get_latest_build(
+        &self,
+        product_id: &str,
+        platform: Platform,
+        branch: Option<&str>,
+        password: Option<String>,
+    ) -> Result<Option<Build>, errors::Error> {
+        let private = password.is_some();
+        let builds = self.get_builds(product_id, platform, password).await?;
+        let latest = match private {
+            true => builds.latest(branch),
+            false => builds.latest_public(branch),
+        };
+        Ok(latest.cloned())
+    }
+
+    /// Check for updates of installed games  
+    /// Results are returned in the order of `installed`
+    pub async fn update_check(
+        &self,
+        installed: &[InstalledGame],
+    ) -> Vec<Result<UpdateStatus, errors::Error>> {
+        updates::update_check(self, installed).await
+    }
+
+    /// Estimate the download size and disk usage change of the update  
+    /// See [`updates::estimate_update`]
+    pub async fn estimate_update(
+        &self,
+        old_manifest: &Manifest,
+        old_build_id: &str,
+        new_manifest: &Manifest,
+        new_build_id: &str,
+        options: &EstimateOptions,
+    ) -> Result<UpdateEstimate, errors::Error> {
+        updates::estimate_update(
+            &self.reqwest_client,
+            old_manifest,
+            old_build_id,
+            new_manifest,
+            new_build_id,
+            options,
+        )
+        .await
+    }
+
     /// Get manifest for the build obtained with [`Core::get_builds`]
     pub async fn get_manifest(&self, build: &Build) -> Result<Manifest, errors::Error> {
         for endpoint in build.urls() {
@@ -15,6 +15,12 @@ pub enum ErrorKind {
     Io,
     Zlib,
     Xdelta(String),
+    /// Downloaded data doesn't match the manifest,
+    /// `chunk` is the index of the chunk or None for the whole file
+    ChecksumMismatch {
+        path: String,
+        chunk: Option<usize>,
+    },
     MaximumRetries,
     #[cfg(feature = "downloader")]
     DownloaderBuilder,
@@ -56,6 +62,13 @@ impl Display for Error {
             ErrorKind::MaximumRetries => f.write_str("maximum retries exceeded"),
             ErrorKind::Zlib => f.write_str("zlib error"),
             ErrorKind::Xdelta(msg) => f.write_fmt(format_args!("decompression error {}", msg)),
+            ErrorKind::ChecksumMismatch { path, chunk } => match chunk {
+                Some(chunk) => f.write_fmt(format_args!(
+                    "checksum mismatch in chunk {} of {}",
+                    chunk, path
+                )),
+                None => f.write_fmt(format_args!("checksum mismatch in {}", path)),
+            },
             ErrorKind::Cancelled => f.write_str("operation was cancelled"),
             ErrorKind::Task => f.write_str("error occured in the task executor"),
             #[cfg(feature = "downloader")]
@@ -127,6 +140,17 @@ pub(crate) fn xdelta_error(msg: String) -> Error {
     Error::new(ErrorKind::Xdelta(msg), None::<BoxError>)
 }
 
+#[cfg(feature = "downloader")]
+pub(crate) fn checksum_mismatch_error(path: &str, chunk: Option<usize>) -> Error {
+    Error::new(
+        ErrorKind::ChecksumMismatch {
+            path: path.to_owned(),
+            chunk,
+        },
+        None::<BoxError>,
+    )
+}
+
 pub(crate) fn io_error<E: Into<BoxError>>(err: E) -> Error {
     Error::new(ErrorKind::Io, Some(err))
 }
@@ -5,6 +5,7 @@ mod core;
 pub mod errors;
 pub mod gameplay;
 pub mod library;
+pub mod products;
 pub mod user;
 pub mod utils;
 
@@ -13,9 +14,13 @@ pub mod utils;
 mod xdelta;
 
 pub use crate::errors::Error;
-pub use content_system::types::Platform;
+pub use content_system::types::{OsBitness, Platform};
 pub use core::Core;
 pub use core::CoreEvent;
 
 #[cfg(feature = "downloader")]
 pub use content_system::downloader::Downloader;
+#[cfg(feature = "downloader")]
+pub use products::downloader::FileDownloader;
+#[cfg(feature = "downloader")]
+pub use products::linux::LinuxInstaller;
@@ -0,0 +1,367 @@
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use percent_encoding::percent_decode_str;
+use reqwest::{header, StatusCode};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedSender};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::content_system::downloader::progress::{
+    self, DownloadProgress, DownloadState, WorkerUpdate,
+};
+use crate::content_system::downloader::verify::calculate_md5;
+use crate::errors::{cancelled_error, io_error, maximum_retries_error, request_error};
+use crate::utils::reqwest_exponential_backoff;
+use crate::{Core, Error};
+
+use super::types::{DownloadFile, FileChecksum};
+
+const MAX_RETRIES: i32 = 5;
+
+/// Downloader for standalone files served through downlinks e.g goodies or offline installers  
+/// Files are saved under their CDN names and interrupted downloads are resumed.  
+/// If the downlink publishes a checksum, the file is verified against it
+pub struct FileDownloader {
+    core: Core,
+    files: Vec<DownloadFile>,
+    destination: PathBuf,
+
+    progress_channel_sender: Sender<DownloadState>,
+    progress_channel_receiver: Option<Receiver<DownloadState>>,
+
+    cancellation_token: CancellationToken,
+}
+
+impl FileDownloader {
+    /// * `destination` - directory the files will be saved to
+    pub fn new(core: Core, destination: PathBuf, files: Vec<DownloadFile>) -> Self {
+        let (progress_channel_sender, progress_channel_receiver) = tokio::sync::mpsc::channel(5);
+        Self {
+            core,
+            files,
+            destination,
+            progress_channel_sender,
+            progress_channel_receiver: Some(progress_channel_receiver),
+            cancellation_token: CancellationToken::new(),
+        }
+    }
+
+    /// Returns a cancellation token that allows to stop the download
+    pub fn get_cancellation(&self) -> CancellationToken {
+        self.cancellation_token.clone()
+    }
+
+    /// Returns a receiver for progress events
+    /// leaving None in it's place, meaning this
+    /// function will return Some only once
+    pub fn take_progress_receiver(&mut self) -> Option<Receiver<DownloadState>> {
+        self.progress_channel_receiver.take()
+    }
+
+    /// Download the files, returns paths of the downloaded files
+    pub async fn download(&self) -> Result<Vec<PathBuf>, Error> {
+        let _ = self
+            .progress_channel_sender
+            .send(DownloadState::Preparing)
+            .await;
+
+        if !self.destination.exists() {
+            fs::create_dir_all(&self.destination)
+                .await
+                .map_err(io_error)?;
+        }
+
+        let total = self.files.iter().fold(0, |acc, f| acc + f.size());
+        let download_progress = Arc::new(Mutex::new(DownloadProgress {
+            total_download: total,
+            total_size: total,
+            ..Default::default()
+        }));
+
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<WorkerUpdate>();
+        let progress_report =
+            progress::spawn_reporter(download_progress, rx, self.progress_channel_sender.clone());
+
+        let result = tokio::select! {
+            result = download_files(&self.core, &self.files, &self.destination, &tx) => result,
+            _ = self.cancellation_token.cancelled() => Err(cancelled_error()),
+        };
+
+        if let Err(err) = progress_report.await {
+            log::debug!("Failed to wait for the progress {}", err);
+        }
+
+        result
+    }
+}
+
+/// Download files through their downlinks, verifying them when checksums are available
+pub(crate) async fn download_files(
+    core: &Core,
+    files: &[DownloadFile],
+    destination: &Path,
+    tx: &UnboundedSender<WorkerUpdate>,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = Vec::with_capacity(files.len());
+    for file in files {
+        let downlink = core.get_downlink(file).await?;
+        let checksum = match downlink.checksum() {
+            Some(url) if !url.is_empty() => {
+                match super::get_checksum(core.reqwest_client(), url).await {
+                    Ok(checksum) => Some(checksum),
+                    Err(err) => {
+                        log::warn!("Failed to get checksum for {}: {}", file.id(), err);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        log::debug!("Downloading file {}", file.id());
+        let path = download_file(
+            core.reqwest_client(),
+            downlink.downlink(),
+            file,
+            checksum.as_ref(),
+            destination,
+            tx,
+        )
+        .await?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Extract the file name from the url the downlink redirected to  
+/// Names that could point outside of the destination directory are rejected
+fn file_name_from_url(url: &reqwest::Url) -> Option<String> {
+    let segment = url.path_segments()?.next_back()?;
+    let name = percent_decode_str(segment).decode_utf8_lossy().to_string();
+    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+        return None;
+    }
+    let mut components = Path::new(&name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Some(name),
+        _ => None,
+    }
+}
+
+/// Verify the downloaded file against the checksum  
+/// returns offset from which the file needs to be downloaded again
+async fn verify_download(path: &Path, checksum: &FileChecksum) -> Result<Option<u64>, Error> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .open(path)
+        .await
+        .map_err(io_error)?;
+    let size = file.metadata().await.map_err(io_error)?.len();
+
+    if checksum.chunks().is_empty() {
+        let md5 = calculate_md5(&mut file, 0, None).await.map_err(io_error)?;
+        return Ok((&md5 != checksum.md5()).then_some(0));
+    }
+
+    for chunk in checksum.chunks() {
+        let chunk_size = (chunk.to() - chunk.from() + 1) as i64;
+        let md5 = calculate_md5(&mut file, *chunk.from() as i64, Some(chunk_size))
+            .await
+            .map_err(io_error)?;
+        if &md5 != chunk.hash() {
+            return Ok(Some(*chunk.from()));
+        }
+    }
+
+    if size != *checksum.total_size() {
+        let valid_size = size.min(*checksum.total_size());
+        return Ok(Some(valid_size));
+    }
+
+    Ok(None)
+}
+
+pub(crate) async fn download_file(
+    reqwest_client: &reqwest::Client,
+    downlink: &str,
+    file: &DownloadFile,
+    checksum: Option<&FileChecksum>,
+    destination: &Path,
+    result_report: &UnboundedSender<WorkerUpdate>,
+) -> Result<PathBuf, Error> {
+    // Follow the redirects to learn the actual file location
+    let response = reqwest_exponential_backoff(reqwest_client.get(downlink))
+        .await
+        .map_err(request_error)?;
+    let response = response.error_for_status().map_err(request_error)?;
+    let url = response.url().clone();
+    let file_name = file_name_from_url(&url).unwrap_or_else(|| file.id().clone());
+
+    let file_path = destination.join(&file_name);
+    let mut download_path = OsString::from(file_path.as_os_str());
+    download_path.push(".download");
+    let download_path = PathBuf::from(download_path);
+
+    if file_path.exists() {
+        let valid_size = match checksum {
+            Some(checksum) => verify_download(&file_path, checksum).await?,
+            None => None,
+        };
+        let Some(valid_size) = valid_size else {
+            log::debug!("{} already downloaded", file_name);
+            let _ = result_report.send(WorkerUpdate::Download(*file.size() as usize));
+            let _ = result_report.send(WorkerUpdate::Write(*file.size() as usize));
+            return Ok(file_path);
+        };
+        // Continue from the valid part of the existing file
+        log::warn!(
+            "Checksum mismatch for downloaded {}, resuming from {}",
+            file_name,
+            valid_size
+        );
+        fs::rename(&file_path, &download_path)
+            .await
+            .map_err(io_error)?;
+        let file_handle = fs::OpenOptions::new()
+            .write(true)
+            .open(&download_path)
+            .await
+            .map_err(io_error)?;
+        file_handle.set_len(valid_size).await.map_err(io_error)?;
+    }
+
+    let mut response = Some(response);
+    let mut reported: u64 = 0;
+    let mut failed = 0;
+    loop {
+        let offset = match fs::metadata(&download_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+
+        let res = match response.take() {
+            Some(res) if offset == 0 => res,
+            _ => {
+                let mut request = reqwest_client.get(url.clone());
+                if offset > 0 {
+                    request = request.header(header::RANGE, format!("bytes={}-", offset));
+                }
+                reqwest_exponential_backoff(request)
+                    .await
+                    .map_err(request_error)?
+                    .error_for_status()
+                    .map_err(request_error)?
+            }
+        };
+
+        let resumed = res.status() == StatusCode::PARTIAL_CONTENT;
+        let mut file_handle = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&download_path)
+            .await
+            .map_err(io_error)?;
+
+        let mut position = if resumed { offset } else { 0 };
+        if position > reported {
+            let _ = result_report.send(WorkerUpdate::Download((position - reported) as usize));
+            let _ = result_report.send(WorkerUpdate::Write((position - reported) as usize));
+            reported = position;
+        }
+
+        let mut stream = res.bytes_stream();
+        let mut stream_error = None;
+        while let Some(item) = stream.next().await {
+            let chunk = match item {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    stream_error = Some(err);
+                    break;
+                }
+            };
+            file_handle.write_all(&chunk).await.map_err(io_error)?;
+            position += chunk.len() as u64;
+            if position > reported {
+                let new_bytes = (position - reported) as usize;
+                let _ = result_report.send(WorkerUpdate::Download(new_bytes));
+                let _ = result_report.send(WorkerUpdate::Write(new_bytes));
+                reported = position;
+            }
+        }
+        file_handle.flush().await.map_err(io_error)?;
+        drop(file_handle);
+
+        if let Some(err) = stream_error {
+            log::warn!("Download of {} interrupted: {}", file_name, err);
+        } else {
+            let Some(checksum) = checksum else {
+                break;
+            };
+            match verify_download(&download_path, checksum).await? {
+                None => break,
+                Some(valid_size) => {
+                    log::warn!(
+                        "Checksum mismatch for {}, resuming from {}",
+                        file_name,
+                        valid_size
+                    );
+                    let file_handle = fs::OpenOptions::new()
+                        .write(true)
+                        .open(&download_path)
+                        .await
+                        .map_err(io_error)?;
+                    file_handle.set_len(valid_size).await.map_err(io_error)?;
+                    if reported > valid_size {
+                        let discarded = (reported - valid_size) as usize;
+                        let _ = result_report.send(WorkerUpdate::Discarded(discarded));
+                        reported = valid_size;
+                    }
+                }
+            }
+        }
+
+        failed += 1;
+        if failed == MAX_RETRIES {
+            return Err(maximum_retries_error());
+        }
+        tokio::time::sleep(Duration::from_secs(failed as u64 * 2)).await;
+    }
+
+    fs::rename(&download_path, &file_path)
+        .await
+        .map_err(io_error)?;
+    Ok(file_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_name() {
+        let url = reqwest::Url::parse(
+            "https://gog-cdn.example.com/secure/game/manual_%28english%29.pdf?token=abc",
+        )
+        .unwrap();
+        assert_eq!(
+            file_name_from_url(&url),
+            Some("manual_(english).pdf".to_string())
+        );
+
+        let url = reqwest::Url::parse("https://gog-cdn.example.com/").unwrap();
+        assert_eq!(file_name_from_url(&url), None);
+
+        for path in ["..%2F..%2Fx", "..", "a%5C..%5Cb", "%2Fetc%2Fpasswd"] {
+            let url =
+                reqwest::Url::parse(&format!("https://gog-cdn.example.com/{}", path)).unwrap();
+            assert_eq!(file_name_from_url(&url), None, "{}", path);
+        }
+    }
+}
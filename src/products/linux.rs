@@ -0,0 +1,450 @@
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use crate::content_system::downloader::progress::{
+    self, DownloadProgress, DownloadState, WorkerUpdate,
+};
+use crate::content_system::downloader::utils::symlink;
+use crate::errors::{
+    cancelled_error, io_error, not_ready_error, serde_error, task_error, EmptyResult,
+};
+use crate::{Core, Error, Platform};
+
+use super::downloader::download_files;
+use super::types::Installer;
+
+/// Directory inside of the MojoSetup archive that holds the game files
+const PAYLOAD_PREFIX: &str = "data/noarch/";
+pub const INSTALL_RECORD_FILE: &str = ".gog-warp-install.json";
+
+/// Saved into the install directory after successful installation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstallRecord {
+    pub product_id: String,
+    pub installer_id: String,
+    pub name: String,
+    pub version: Option<String>,
+    pub language: String,
+    pub platform: Platform,
+    /// Installed files relative to the install path
+    pub files: Vec<String>,
+}
+
+/// Installs Linux native games from MojoSetup `.sh` installers
+/// The installer is downloaded, then the game files are extracted from the embedded zip
+pub struct LinuxInstaller {
+    core: Core,
+    product_id: String,
+    installer: Installer,
+    install_path: PathBuf,
+    keep_installer: bool,
+
+    progress_channel_sender: Sender<DownloadState>,
+    progress_channel_receiver: Option<Receiver<DownloadState>>,
+
+    cancellation_token: CancellationToken,
+}
+
+impl LinuxInstaller {
+    /// * `installer` - linux installer obtained with [`Core::get_installers`]
+    /// * `install_path` - directory the game will be installed to
+    pub fn new(core: Core, product_id: &str, installer: Installer, install_path: PathBuf) -> Self {
+        let (progress_channel_sender, progress_channel_receiver) = tokio::sync::mpsc::channel(5);
+        Self {
+            core,
+            product_id: product_id.to_string(),
+            installer,
+            install_path,
+            keep_installer: false,
+            progress_channel_sender,
+            progress_channel_receiver: Some(progress_channel_receiver),
+            cancellation_token: CancellationToken::new(),
+        }
+    }
+
+    /// Don't remove the downloaded `.sh` file after the installation
+    pub fn keep_installer(mut self) -> Self {
+        self.keep_installer = true;
+        self
+    }
+
+    /// Returns a cancellation token that allows to stop the installation
+    pub fn get_cancellation(&self) -> CancellationToken {
+        self.cancellation_token.clone()
+    }
+
+    /// Returns a receiver for progress events
+    /// leaving None in it's place, meaning this
+    /// function will return Some only once
+    pub fn take_progress_receiver(&mut self) -> Option<Receiver<DownloadState>> {
+        self.progress_channel_receiver.take()
+    }
+
+    /// Download and extract the installer
+    pub async fn install(&self) -> Result<InstallRecord, Error> {
+        if self.installer.platform() != Some(Platform::Linux) {
+            return Err(not_ready_error("installer is not a linux installer"));
+        }
+        let _ = self
+            .progress_channel_sender
+            .send(DownloadState::Preparing)
+            .await;
+
+        let tmp_path = self.install_path.join("!Temp");
+        fs::create_dir_all(&tmp_path).await.map_err(io_error)?;
+
+        let total = *self.installer.total_size();
+        let download_progress = Arc::new(Mutex::new(DownloadProgress {
+            total_download: total,
+            total_size: total,
+            ..Default::default()
+        }));
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<WorkerUpdate>();
+        let progress_report =
+            progress::spawn_reporter(download_progress, rx, self.progress_channel_sender.clone());
+
+        let result = tokio::select! {
+            result = self.download_and_extract(&tmp_path, &tx) => result,
+            _ = self.cancellation_token.cancelled() => Err(cancelled_error()),
+        };
+
+        drop(tx);
+        if let Err(err) = progress_report.await {
+            log::debug!("Failed to wait for the progress {}", err);
+        }
+
+        result
+    }
+
+    async fn download_and_extract(
+        &self,
+        tmp_path: &Path,
+        tx: &tokio::sync::mpsc::UnboundedSender<WorkerUpdate>,
+    ) -> Result<InstallRecord, Error> {
+        let paths = download_files(&self.core, self.installer.files(), tmp_path, tx).await?;
+        let Some(script) = paths
+            .iter()
+            .find(|p| p.extension().is_some_and(|e| e == "sh"))
+        else {
+            return Err(not_ready_error("installer doesn't contain .sh file"));
+        };
+
+        log::info!("Extracting {}", script.display());
+        let script = script.clone();
+        let install_path = self.install_path.clone();
+        let progress_sender = self.progress_channel_sender.clone();
+        let cancellation_token = self.cancellation_token.clone();
+        let files = tokio::task::spawn_blocking(move || {
+            let file = File::open(&script).map_err(io_error)?;
+            extract_payload(file, &install_path, &cancellation_token, |progress| {
+                let _ = progress_sender.try_send(DownloadState::Extracting(progress));
+            })
+        })
+        .await
+        .map_err(task_error)??;
+
+        let record = InstallRecord {
+            product_id: self.product_id.clone(),
+            installer_id: self.installer.id().clone(),
+            name: self.installer.name().clone(),
+            version: self.installer.version().clone(),
+            language: self.installer.language().clone(),
+            platform: Platform::Linux,
+            files,
+        };
+        write_install_record(&self.install_path, &record).await?;
+
+        if !self.keep_installer {
+            fs::remove_dir_all(tmp_path).await.map_err(io_error)?;
+        }
+
+        Ok(record)
+    }
+}
+
+async fn write_install_record(install_path: &Path, record: &InstallRecord) -> EmptyResult {
+    let data = serde_json::to_vec_pretty(record).map_err(serde_error)?;
+    let mut file = fs::File::create(install_path.join(INSTALL_RECORD_FILE))
+        .await
+        .map_err(io_error)?;
+    file.write_all(&data).await.map_err(io_error)?;
+    file.flush().await.map_err(io_error)?;
+    Ok(())
+}
+
+/// Read the record of previous installation
+pub async fn read_install_record(install_path: &Path) -> Result<InstallRecord, Error> {
+    let data = fs::read(install_path.join(INSTALL_RECORD_FILE))
+        .await
+        .map_err(io_error)?;
+    serde_json::from_slice(&data).map_err(serde_error)
+}
+
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Extracts the `data/noarch` directory of the zip embedded in the installer script
+/// Returns the list of extracted files
+fn extract_payload<R, F>(
+    reader: R,
+    install_path: &Path,
+    cancellation_token: &CancellationToken,
+    mut report: F,
+) -> Result<Vec<String>, Error>
+where
+    R: Read + Seek,
+    F: FnMut(f32),
+{
+    // The zip archive is appended to the makeself script,
+    // the prepended data is accounted for by the zip reader
+    let mut archive = zip::ZipArchive::new(reader).map_err(io_error)?;
+
+    let mut total_size: u64 = 0;
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index).map_err(io_error)?;
+        if entry.name().starts_with(PAYLOAD_PREFIX) {
+            total_size += entry.size();
+        }
+    }
+
+    std::fs::create_dir_all(install_path).map_err(io_error)?;
+    let install_root = install_path.canonicalize().map_err(io_error)?;
+
+    let mut files = Vec::new();
+    let mut processed: u64 = 0;
+    for index in 0..archive.len() {
+        if cancellation_token.is_cancelled() {
+            return Err(cancelled_error());
+        }
+        let mut entry = archive.by_index(index).map_err(io_error)?;
+        let Some(entry_path) = entry.enclosed_name() else {
+            log::warn!("Skipping unsafe path {}", entry.name());
+            continue;
+        };
+        let Ok(relative_path) = entry_path.strip_prefix(PAYLOAD_PREFIX) else {
+            continue;
+        };
+        if relative_path.as_os_str().is_empty() {
+            continue;
+        }
+        let file_path = install_path.join(relative_path);
+
+        // Directories created by earlier symlink entries must not lead outside
+        let parent = match entry.is_dir() {
+            true => file_path.as_path(),
+            false => file_path.parent().unwrap_or(install_path),
+        };
+        if !is_enclosed(&install_root, parent)? {
+            log::warn!(
+                "Skipping {}, it leads outside of install path",
+                entry.name()
+            );
+            continue;
+        }
+        if entry.is_dir() {
+            std::fs::create_dir_all(&file_path).map_err(io_error)?;
+            continue;
+        }
+        std::fs::create_dir_all(parent).map_err(io_error)?;
+        if file_path.symlink_metadata().is_ok_and(|m| m.is_symlink()) {
+            std::fs::remove_file(&file_path).map_err(io_error)?;
+        }
+
+        let mode = entry.unix_mode();
+        if mode.is_some_and(|m| m & S_IFMT == S_IFLNK) {
+            let mut target = String::new();
+            entry.read_to_string(&mut target).map_err(io_error)?;
+            if !is_link_enclosed(relative_path, Path::new(&target)) {
+                log::warn!("Skipping unsafe symlink {} -> {}", entry.name(), target);
+                continue;
+            }
+            if file_path.symlink_metadata().is_ok() {
+                std::fs::remove_file(&file_path).map_err(io_error)?;
+            }
+            symlink(file_path.to_str().unwrap(), &target)?;
+        } else {
+            let mut file = File::create(&file_path).map_err(io_error)?;
+            std::io::copy(&mut entry, &mut file).map_err(io_error)?;
+            #[cfg(unix)]
+            if let Some(mode) = mode {
+                use std::os::unix::fs::PermissionsExt;
+                let permissions = std::fs::Permissions::from_mode(mode & 0o777);
+                std::fs::set_permissions(&file_path, permissions).map_err(io_error)?;
+            }
+        }
+
+        processed += entry.size();
+        if total_size > 0 {
+            report(processed as f32 / total_size as f32 * 100.0);
+        }
+        files.push(relative_path.to_string_lossy().to_string());
+    }
+
+    Ok(files)
+}
+
+/// Whether the symlink target, resolved lexically from the directory of the link,
+/// stays inside of the install directory  
+/// `link` is relative to the install directory, absolute targets are rejected
+fn is_link_enclosed(link: &Path, target: &Path) -> bool {
+    let mut depth = link.parent().map_or(0, |p| p.components().count());
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(parent) => depth = parent,
+                None => return false,
+            },
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Whether `path` resolves to a location under `root`  
+/// The path is checked through its closest existing ancestor, which may be a symlink
+fn is_enclosed(root: &Path, path: &Path) -> Result<bool, Error> {
+    let mut existing = path;
+    while !existing.exists() {
+        match existing.parent() {
+            Some(parent) => existing = parent,
+            None => return Ok(false),
+        }
+    }
+    let resolved = existing.canonicalize().map_err(io_error)?;
+    Ok(resolved.starts_with(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+
+    fn build_installer() -> Vec<u8> {
+        let mut data = b"#!/bin/sh\n# makeself header\nexit 0\n".to_vec();
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.add_directory("data/noarch/game/", SimpleFileOptions::default())
+            .unwrap();
+        zip.start_file(
+            "data/noarch/start.sh",
+            SimpleFileOptions::default().unix_permissions(0o755),
+        )
+        .unwrap();
+        zip.write_all(b"#!/bin/sh\n").unwrap();
+        zip.start_file(
+            "data/noarch/game/data.pak",
+            SimpleFileOptions::default().unix_permissions(0o644),
+        )
+        .unwrap();
+        zip.write_all(&[1, 2, 3, 4]).unwrap();
+        zip.start_file("scripts/config.lua", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"-- mojosetup").unwrap();
+        data.extend(zip.finish().unwrap().into_inner());
+        data
+    }
+
+    #[test]
+    fn extract_noarch() {
+        let install_path = crate::utils::test_dir("linux-extract");
+
+        let mut reports = 0;
+        let mut files = extract_payload(
+            Cursor::new(build_installer()),
+            &install_path,
+            &CancellationToken::new(),
+            |_| reports += 1,
+        )
+        .expect("Extraction failed");
+        files.sort();
+
+        assert_eq!(files, vec!["game/data.pak", "start.sh"]);
+        assert_eq!(reports, 2);
+        assert!(!install_path.join("scripts").exists());
+        assert_eq!(
+            std::fs::read(install_path.join("game/data.pak")).unwrap(),
+            vec![1, 2, 3, 4]
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(install_path.join("start.sh"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+
+        std::fs::remove_dir_all(&install_path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_escaping_symlinks() {
+        let root = crate::utils::test_dir("linux-symlink");
+        let install_path = root.join("install");
+        let outside = root.join("outside");
+        std::fs::create_dir_all(&install_path).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        // Left behind in the install directory by something else
+        std::os::unix::fs::symlink(&outside, install_path.join("existing")).unwrap();
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        zip.add_symlink("data/noarch/game", outside.to_str().unwrap(), options)
+            .unwrap();
+        zip.add_symlink("data/noarch/up", "../outside", options)
+            .unwrap();
+        zip.add_symlink("data/noarch/start", "game/start.sh", options)
+            .unwrap();
+        zip.add_symlink("data/noarch/lib/foo.so", "../lib64/foo.so", options)
+            .unwrap();
+        zip.add_symlink("data/noarch/lib/up", "../../outside", options)
+            .unwrap();
+        zip.start_file("data/noarch/game/.bashrc", options).unwrap();
+        zip.write_all(b"evil").unwrap();
+        zip.start_file("data/noarch/existing/.bashrc", options)
+            .unwrap();
+        zip.write_all(b"evil").unwrap();
+        zip.add_directory("data/noarch/existing/dir/", options)
+            .unwrap();
+        let data = zip.finish().unwrap().into_inner();
+
+        let mut files = extract_payload(
+            Cursor::new(data),
+            &install_path,
+            &CancellationToken::new(),
+            |_| {},
+        )
+        .expect("Extraction failed");
+        files.sort();
+
+        assert_eq!(files, vec!["game/.bashrc", "lib/foo.so", "start"]);
+        assert!(std::fs::read_dir(&outside).unwrap().next().is_none());
+        assert!(!install_path.join("up").exists());
+        assert!(!install_path.join("lib/up").exists());
+        assert_eq!(
+            std::fs::read_link(install_path.join("lib/foo.so")).unwrap(),
+            PathBuf::from("../lib64/foo.so")
+        );
+        assert!(!install_path.join("game").is_symlink());
+        assert_eq!(
+            std::fs::read_link(install_path.join("start")).unwrap(),
+            PathBuf::from("game/start.sh")
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
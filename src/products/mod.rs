@@ -0,0 +1,93 @@
+pub mod types;
+
+#[cfg(feature = "downloader")]
+pub mod downloader;
+#[cfg(feature = "downloader")]
+pub mod linux;
+
+use crate::auth::types::Token;
+use crate::constants::domains::GOG_API;
+use crate::errors::request_error;
+#[cfg(feature = "downloader")]
+use crate::errors::serde_error;
+use crate::utils::reqwest_exponential_backoff;
+use crate::Error;
+use reqwest::{Client, Url};
+
+pub(crate) async fn get_product_details(
+    client: &Client,
+    token: Token,
+    product_id: &str,
+) -> Result<types::ProductDetails, Error> {
+    let url = format!("{}/products/{}", GOG_API, product_id);
+    let url = Url::parse_with_params(&url, [("expand", "downloads")]).unwrap();
+    let response = reqwest_exponential_backoff(client.get(url).bearer_auth(token.access_token()))
+        .await
+        .map_err(request_error)?;
+    let response = response.error_for_status().map_err(request_error)?;
+    let data = response.json().await.map_err(request_error)?;
+    Ok(data)
+}
+
+pub(crate) async fn get_downlink(
+    client: &Client,
+    token: Token,
+    downlink: &str,
+) -> Result<types::Downlink, Error> {
+    let response =
+        reqwest_exponential_backoff(client.get(downlink).bearer_auth(token.access_token()))
+            .await
+            .map_err(request_error)?;
+    let response = response.error_for_status().map_err(request_error)?;
+    let data = response.json().await.map_err(request_error)?;
+    Ok(data)
+}
+
+/// Follows the redirects of the download link returning the final CDN url
+pub(crate) async fn resolve_url(client: &Client, url: &str) -> Result<Url, Error> {
+    let response = reqwest_exponential_backoff(client.get(url))
+        .await
+        .map_err(request_error)?;
+    let response = response.error_for_status().map_err(request_error)?;
+    Ok(response.url().clone())
+}
+
+#[cfg(feature = "downloader")]
+pub(crate) async fn get_checksum(
+    client: &Client,
+    checksum_url: &str,
+) -> Result<types::FileChecksum, Error> {
+    let response = reqwest_exponential_backoff(client.get(checksum_url))
+        .await
+        .map_err(request_error)?;
+    let response = response.error_for_status().map_err(request_error)?;
+    let data = response.text().await.map_err(request_error)?;
+    parse_checksum(&data)
+}
+
+#[cfg(feature = "downloader")]
+fn parse_checksum(data: &str) -> Result<types::FileChecksum, Error> {
+    quick_xml::de::from_str(data).map_err(serde_error)
+}
+
+#[cfg(all(test, feature = "downloader"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_xml() {
+        let data = r#"<file name="setup_game_1.0.exe" available="1" notavailablemsg="" md5="8d2a6d2bd8b3a1e5f0e1a0a3b7d8f6e1" chunks="2" timestamp="2023-01-01 10:00:00" total_size="15728640">
+	<chunk id="0" from="0" to="10485759" method="md5">5e2f1e0a8d2b4c6a1e3f5a7b9c0d2e4f</chunk>
+	<chunk id="1" from="10485760" to="15728639" method="md5">a1b2c3d4e5f60718293a4b5c6d7e8f90</chunk>
+</file>"#;
+        let checksum = parse_checksum(data).expect("Failed to parse checksum");
+        assert_eq!(checksum.name(), "setup_game_1.0.exe");
+        assert_eq!(*checksum.total_size(), 15728640);
+        assert_eq!(checksum.chunks().len(), 2);
+        assert_eq!(*checksum.chunks()[1].from(), 10485760);
+        assert_eq!(
+            checksum.chunks()[1].hash(),
+            "a1b2c3d4e5f60718293a4b5c6d7e8f90"
+        );
+    }
+}
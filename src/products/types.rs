@@ -0,0 +1,120 @@
+use derive_getters::Getters;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::content_system::languages;
+use crate::Platform;
+
+/// Ids in the products api are either numbers or strings depending on the download type
+fn string_or_number<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(s) => Ok(s),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        _ => Err(serde::de::Error::custom("expected string or number")),
+    }
+}
+
+#[derive(Serialize, Deserialize, Getters, Debug)]
+pub struct ProductDetails {
+    id: u64,
+    title: String,
+    downloads: ProductDownloads,
+}
+
+#[derive(Serialize, Deserialize, Getters, Default, Debug)]
+pub struct ProductDownloads {
+    #[serde(default)]
+    installers: Vec<Installer>,
+    #[serde(default)]
+    patches: Vec<Installer>,
+    #[serde(default)]
+    language_packs: Vec<Installer>,
+    #[serde(default)]
+    bonus_content: Vec<BonusContent>,
+}
+
+/// Offline installer, consists of the setup file and optional `.bin` parts
+#[derive(Serialize, Deserialize, Getters, Clone, Debug)]
+pub struct Installer {
+    id: String,
+    name: String,
+    /// One of `windows`, `mac` or `linux`
+    os: String,
+    #[serde(deserialize_with = "languages::serde_single_language")]
+    language: String,
+    language_full: String,
+    version: Option<String>,
+    total_size: u64,
+    files: Vec<DownloadFile>,
+}
+
+impl Installer {
+    /// Returns the platform of the installer, None if it's not supported
+    pub fn platform(&self) -> Option<Platform> {
+        match self.os.as_str() {
+            "windows" => Some(Platform::Windows),
+            "mac" | "osx" => Some(Platform::OsX),
+            "linux" => Some(Platform::Linux),
+            _ => None,
+        }
+    }
+}
+
+/// Goodies attached to the product e.g manuals, soundtracks or wallpapers
+#[derive(Serialize, Deserialize, Getters, Clone, Debug)]
+pub struct BonusContent {
+    #[serde(deserialize_with = "string_or_number")]
+    id: String,
+    name: String,
+    /// Category of the goodie e.g `manuals`, `audio`, `wallpapers`
+    #[serde(rename = "type")]
+    bonus_type: String,
+    count: u32,
+    total_size: u64,
+    files: Vec<DownloadFile>,
+}
+
+#[derive(Serialize, Deserialize, Getters, Clone, Debug)]
+pub struct DownloadFile {
+    #[serde(deserialize_with = "string_or_number")]
+    id: String,
+    size: u64,
+    downlink: String,
+}
+
+/// Response of the downlink endpoint  
+/// `downlink` redirects to the actual CDN location of the file
+#[derive(Serialize, Deserialize, Getters, Clone, Debug)]
+pub struct Downlink {
+    downlink: String,
+    checksum: Option<String>,
+}
+
+/// Checksum file published for installer parts
+#[derive(Deserialize, Getters, Clone, Debug)]
+pub struct FileChecksum {
+    #[serde(rename = "@name")]
+    name: String,
+    #[serde(rename = "@md5")]
+    md5: String,
+    #[serde(rename = "@total_size")]
+    total_size: u64,
+    #[serde(rename = "chunk", default)]
+    chunks: Vec<ChecksumChunk>,
+}
+
+#[derive(Deserialize, Getters, Clone, Debug)]
+pub struct ChecksumChunk {
+    #[serde(rename = "@id")]
+    id: u32,
+    #[serde(rename = "@from")]
+    from: u64,
+    #[serde(rename = "@to")]
+    to: u64,
+    #[serde(rename = "@method")]
+    method: String,
+    #[serde(rename = "$text")]
+    hash: String,
+}
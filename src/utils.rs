@@ -76,6 +76,15 @@ pub async fn reqwest_exponential_backoff(
     }
 }
 
+/// Unique temporary directory for a test, so that concurrent test runs don't clobber each other
+#[cfg(test)]
+pub(crate) fn test_dir(name: &str) -> std::path::PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("gog-warp-{}-{}-{}", name, std::process::id(), id))
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -131,6 +140,8 @@ mod tests {
             fallback_only: false,
         };
 
+        assert_eq!(sample.expires_at().unwrap().timestamp(), 1717011195);
+
         let result = assemble_url(&sample, "f1/d4/f1d41c76eb9639d2f8c1d3fd2057d7f1");
         assert_eq!(result, "https://gog-cdn-fastly.gog.com/token=nva=1717011195~dirs=4~token=0f76ef3e8f6b5d6baddc4/content-system/v2/store/2034949552/f1/d4/f1d41c76eb9639d2f8c1d3fd2057d7f1")
     }